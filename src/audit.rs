@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::client::Client;
+use crate::config::Config;
+use crate::csv_reader::{Record, RecordMap, Type, CSV};
+use crate::ledger::Ledger;
+use crate::transction::{self, Applied};
+
+// Largest difference the balances may have due to floating point
+const TOLERANCE: f64 = 1e-9;
+
+// The first invariant broken while replaying a csv, with the row that broke it
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub row: usize,
+    // line of the file the row was read from
+    pub line: usize,
+    pub invariant: String,
+    pub record: Record,
+    pub before: Client,
+    pub after: Client,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Audit failed at row {} (line {}): {}",
+            self.row, self.line, self.invariant
+        )?;
+        writeln!(
+            f,
+            "  record: {}, client {}, tx {}, amount {}",
+            self.record.r#type,
+            self.record.client,
+            self.record.tx,
+            match self.record.amount {
+                Some(amount) => amount.to_string(),
+                None => "-".to_string(),
+            }
+        )?;
+        for (name, client) in [("before", &self.before), ("after", &self.after)].iter() {
+            writeln!(
+                f,
+                "  {:<7} client {}, available {:.4}, held {:.4}, total {:.4}, locked {}",
+                format!("{}:", name),
+                client.client,
+                client.available,
+                client.held,
+                client.total,
+                client.locked
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// Returns true if the balances of the clients differ
+fn changed(before: &Client, after: &Client) -> bool {
+    (before.available - after.available).abs() > TOLERANCE
+        || (before.held - after.held).abs() > TOLERANCE
+        || (before.total - after.total).abs() > TOLERANCE
+}
+
+// Disputed and charged back amounts of a tx for a client, kept apart from the ledger.
+// Both clients of a transfer see its disputes.
+#[derive(Debug, Clone, Copy, Default)]
+struct Disputed {
+    open: f64,
+    charged_back: f64,
+}
+
+// Returns the invariant broken by an applied record, if any
+fn check(
+    record: &Record,
+    applied: &Applied,
+    ledger: &Ledger,
+    records: &RecordMap,
+    disputed: &mut HashMap<(u32, u16), Disputed>,
+) -> Option<String> {
+    let Applied { before, after, .. } = applied;
+
+    if (after.total - (after.available + after.held)).abs() > TOLERANCE {
+        return Some(format!(
+            "total {} is not available + held {}",
+            after.total,
+            after.available + after.held
+        ));
+    }
+
+    if after.held < -TOLERANCE {
+        return Some(format!("held {} is negative", after.held));
+    }
+
+    if before.locked && changed(before, after) {
+        return Some(format!("locked client {} was changed", after.client));
+    }
+
+    // a tx can not be disputed for more than its amount
+    if let Some(amount) = applied.amount {
+        let tx = disputed.entry((record.tx, record.client)).or_default();
+        match record.r#type {
+            Type::DISPUTE => tx.open += amount,
+            Type::RESOLVE => tx.open -= amount,
+            Type::CHARGEBACK => {
+                tx.open -= amount;
+                tx.charged_back += amount;
+            }
+            _ => (),
+        }
+
+        let original = records.get(&record.tx).and_then(|tx| tx.amount);
+        if tx.open < -TOLERANCE {
+            return Some(format!(
+                "tx {} is settled for more than was disputed",
+                record.tx
+            ));
+        }
+        if original.is_some_and(|original| tx.open + tx.charged_back > original + TOLERANCE) {
+            return Some(format!(
+                "tx {} is disputed for more than its amount",
+                record.tx
+            ));
+        }
+    }
+
+    if let Err(err) = ledger.check() {
+        return Some(err);
+    }
+
+    None
+}
+
+// Replays the csv and checks the invariants after every row,
+// returns the number of rows or the first violation
+pub fn audit(csv: &CSV, config: &Config) -> Result<usize, Box<Violation>> {
+    let mut ledger = Ledger::default();
+    let mut disputed = HashMap::new();
+
+    for (i, record) in csv.records_vec.iter().enumerate() {
+        for (record, applied) in transction::step(record, &mut ledger, &csv.records_map, config) {
+            if let Some(invariant) =
+                check(&record, &applied, &ledger, &csv.records_map, &mut disputed)
+            {
+                return Err(Box::new(Violation {
+                    row: i + 1,
+                    line: csv.line(i + 1),
+                    invariant,
+                    record,
+                    before: applied.before,
+                    after: applied.after,
+                }));
+            }
+        }
+    }
+
+    Ok(csv.records_vec.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_reader::{Format, Mode, Status};
+
+    fn record(r#type: Type, tx: u32, amount: Option<f64>) -> Record {
+        Record::new(r#type, 1, tx, amount)
+    }
+
+    fn csv(records_vec: Vec<Record>) -> CSV {
+        let mut records_map: RecordMap = HashMap::new();
+        for record in &records_vec {
+            if record.r#type == Type::DEPOSIT || record.r#type == Type::WITHDRAWAL {
+                let mut record = record.clone();
+                record.status = Some(Status::DISPUTED);
+                records_map.insert(record.tx, record);
+            }
+        }
+
+        // rows start after the header line
+        CSV {
+            lines: (2..records_vec.len() + 2).collect(),
+            records_vec,
+            records_map,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_audit_sample() {
+        let csv = CSV::load("sample.csv").expect("Failed load csv file");
+
+        let rows = audit(&csv, &Config::default()).expect("Failed audit");
+
+        assert_eq!(rows, 17);
+    }
+
+    #[test]
+    fn test_audit_disputed_twice() {
+        let csv = csv(vec![
+            record(Type::DEPOSIT, 1, Some(10.0)),
+            record(Type::DISPUTE, 1, Some(4.0)),
+            record(Type::DISPUTE, 1, None),
+            record(Type::DISPUTE, 1, None),
+        ]);
+
+        let rows = audit(&csv, &Config::default()).expect("Failed audit");
+
+        assert_eq!(rows, 4);
+    }
+
+    #[test]
+    fn test_check_disputed_more_than_amount() {
+        let csv = csv(vec![record(Type::DEPOSIT, 1, Some(10.0))]);
+        let ledger = Ledger::default();
+        let mut disputed = HashMap::new();
+        let dispute = record(Type::DISPUTE, 1, None);
+        let applied = Applied {
+            before: Client::default(),
+            after: Client::default(),
+            amount: Some(10.0),
+            fee: 0.0,
+        };
+
+        let first = check(&dispute, &applied, &ledger, &csv.records_map, &mut disputed);
+        let second = check(&dispute, &applied, &ledger, &csv.records_map, &mut disputed);
+
+        assert_eq!(first, None);
+        assert_eq!(
+            second,
+            Some("tx 1 is disputed for more than its amount".to_string())
+        );
+    }
+
+    #[test]
+    fn test_audit_locked() {
+        let csv = csv(vec![
+            record(Type::DEPOSIT, 1, Some(10.0)),
+            record(Type::DISPUTE, 1, None),
+            record(Type::CHARGEBACK, 1, None),
+            record(Type::DEPOSIT, 2, Some(5.0)),
+        ]);
+
+        let violation = audit(&csv, &Config::default()).expect_err("Failed audit");
+
+        assert_eq!(violation.row, 4);
+        assert_eq!(violation.invariant, "locked client 1 was changed");
+
+        let report = violation.to_string();
+        assert!(report.contains("Audit failed at row 4 (line 5)"));
+        assert!(report.contains("record: deposit, client 1, tx 2, amount 5"));
+    }
+
+    #[test]
+    fn test_audit_lenient() {
+        let csv = CSV::parse(
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             depositt,1,2,1.0\n\
+             dispute,1,1,\n\
+             chargeback,1,1,\n\
+             deposit,1,3,5.0\n",
+            Format::Csv,
+            Mode::Lenient(None),
+        )
+        .expect("Failed to load records");
+
+        let violation = audit(&csv, &Config::default()).expect_err("Failed audit");
+
+        // the skipped line is not a row
+        assert_eq!(violation.row, 4);
+        assert_eq!(violation.line, 6);
+    }
+}
@@ -0,0 +1,564 @@
+use chrono::Duration;
+use std::error::Error;
+use std::fmt::Display;
+use std::io;
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::csv_reader::{Format, Mode};
+use crate::currency::Currency;
+use crate::{invoice, statement};
+
+// Name, arguments and description of every command
+const COMMANDS: [(&str, &str, &str); 11] = [
+    (
+        "process",
+        "<file>",
+        "applies the records and writes the balances of every client",
+    ),
+    (
+        "validate",
+        "<file>",
+        "applies the records without writing the balances and writes a summary of the problems",
+    ),
+    (
+        "audit",
+        "<file>",
+        "applies the records and stops at the first row that breaks an invariant",
+    ),
+    (
+        "statement",
+        "<file>",
+        "writes the applied transactions of every client",
+    ),
+    (
+        "invoice",
+        "<file> [directory]",
+        "writes an invoice file per client to the directory (invoices)",
+    ),
+    (
+        "lifecycle",
+        "<file> <tx>",
+        "writes every dispute, resolve and chargeback of the tx",
+    ),
+    (
+        "rejections",
+        "<file>",
+        "writes the records rejected by the limits with a reason code",
+    ),
+    (
+        "replay",
+        "<file>",
+        "applies the lines one at a time like a tcp connection and writes an ack per line",
+    ),
+    (
+        "serve",
+        "[address]",
+        "runs the engine as an http service (127.0.0.1:8080)",
+    ),
+    (
+        "listen",
+        "[address]",
+        "applies the lines sent over tcp connections (127.0.0.1:9000)",
+    ),
+    ("help", "[command]", "writes the usage of a command"),
+];
+
+// Options of every command that applies records
+const OPTIONS: &str = "\
+Options:
+  --input-format <csv|json>      format of the file, json is a record on every line (csv)
+  --format <format>              output format, csv|json for process, csv|text for statement,
+                                 html|text for invoice
+  --lenient                      skips the lines that are not a record instead of stopping,
+                                 validate always does
+  --max-errors <n>               stops after skipping more than n lines, implies --lenient
+  --fees <file>                  fee schedule
+  --limits <file>                limits of deposits and withdrawals
+  --risk <file>                  risk rules
+  --flagged <file>               writes the records flagged by the risk rules to the file
+  --rates <file>                 exchange rates
+  --base <currency>              writes the balances in the currency
+  --threads <n>                  splits the records by client over the threads, 0 uses every core
+  --dispute-window <days>        disputes of older transactions are ignored
+  --dispute-expiry <days>        open disputes are resolved after this long
+  --clearing-rows <rows>         deposits are pending until this many rows follow them
+  --clearing-days <days>         deposits are pending until this long after their timestamp
+  --withdrawal-disputes <policy> how a dispute of a withdrawal is applied
+  --redispute <allow|never|n>    whether a resolved tx can be disputed again
+  --prefix <prefix>              prefix of the invoice numbers (INV-)
+  --start <n>                    number of the first invoice (1)
+  --date <yyyy-mm-dd>            date of the invoices (today)
+  --terms <days>                 payment terms of the invoices (30)
+  --template <file>              template the invoices are rendered with
+  -h, --help                     writes the usage
+  -V, --version                  writes the version
+
+Exit codes:
+  0  success
+  1  a check failed, such as an audit or the balance of the ledger
+  2  the arguments could not be parsed
+  3  the input could not be parsed, or lines of it were skipped
+  4  records were rejected by the limits or the risk rules
+  5  a file or connection could not be read or written
+";
+
+// Exit codes of the application
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    Success = 0,
+    Failure = 1,
+    Usage = 2,
+    Parse = 3,
+    Rejected = 4,
+    Io = 5,
+}
+
+impl Exit {
+    // Returns the exit code of an error of loading or writing a file,
+    // an error that is not of reading or writing is of parsing
+    pub fn of(err: &(dyn Error + 'static)) -> Exit {
+        if err.is::<io::Error>() {
+            return Exit::Io;
+        }
+
+        match (
+            err.downcast_ref::<csv::Error>(),
+            err.downcast_ref::<serde_json::Error>(),
+        ) {
+            (Some(err), _) if err.is_io_error() => Exit::Io,
+            (_, Some(err)) if err.is_io() => Exit::Io,
+            _ => Exit::Parse,
+        }
+    }
+}
+
+// Commands of the application
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Process(Format),
+    Validate,
+    Audit,
+    Statement(statement::Format),
+    Invoice(String, invoice::Options),
+    Lifecycle(u32),
+    Rejections,
+    Replay,
+    Serve(String),
+    Listen(String),
+    // usage of the command, or of the application
+    Help(Option<String>),
+    Version,
+}
+
+// Parsed arguments, the files of the fees, limits, risk rules and rates are loaded by the caller
+#[derive(Debug, Clone)]
+pub struct Cli {
+    pub command: Command,
+    // file of the records, none for the commands that do not read one
+    pub path: Option<String>,
+    pub input: Format,
+    // how lines that are not a record are treated
+    pub mode: Mode,
+    pub config: Config,
+    pub fees: Option<String>,
+    pub limits: Option<String>,
+    pub risk: Option<String>,
+    pub rates: Option<String>,
+    pub flagged: Option<String>,
+    // 0 uses every core
+    pub threads: usize,
+    pub base: Option<Currency>,
+}
+
+impl Cli {
+    fn new(command: Command) -> Cli {
+        Cli {
+            command,
+            path: None,
+            input: Format::Csv,
+            mode: Mode::Strict,
+            config: Config::default(),
+            fees: None,
+            limits: None,
+            risk: None,
+            rates: None,
+            flagged: None,
+            threads: 1,
+            base: None,
+        }
+    }
+}
+
+// Returns the usage of the command, or of the application without one
+pub fn help(command: Option<&str>) -> String {
+    match COMMANDS.iter().find(|(name, ..)| Some(*name) == command) {
+        Some((name, arguments, description)) => format!(
+            "Usage: invoice {} {} [options]\n\nThe {} command {}.\n\n{}",
+            name, arguments, name, description, OPTIONS
+        ),
+        None => {
+            let mut usage = format!(
+                "invoice {}\n\nUsage: invoice <command> [arguments] [options]\n       \
+                 invoice <file> [options], the same as process\n\nCommands:\n",
+                env!("CARGO_PKG_VERSION")
+            );
+            for (name, arguments, description) in COMMANDS {
+                let command = format!("{} {}", name, arguments);
+                usage.push_str(&format!("  {:<30} {}\n", command, description));
+            }
+            usage.push('\n');
+            usage.push_str(OPTIONS);
+
+            usage
+        }
+    }
+}
+
+// Removes an option and its value from the arguments and returns the value
+fn option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let i = match args.iter().position(|arg| arg == name) {
+        None => return Ok(None),
+        Some(i) => i,
+    };
+
+    let value = match args.get(i + 1) {
+        Some(value) => value.clone(),
+        None => return Err(format!("Error: No value provided for {}", name)),
+    };
+    args.drain(i..i + 2);
+
+    Ok(Some(value))
+}
+
+// Removes a flag from the arguments and returns true if it was there
+fn flag(args: &mut Vec<String>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != name);
+
+    args.len() != len
+}
+
+// Removes an option and its value from the arguments and returns the parsed value
+fn parsed<T>(args: &mut Vec<String>, name: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match option(args, name)? {
+        None => Ok(None),
+        Some(value) => match value.parse() {
+            Err(err) => Err(format!(
+                "Error: Invalid value {} for {}, {}",
+                value, name, err
+            )),
+            Ok(value) => Ok(Some(value)),
+        },
+    }
+}
+
+// Removes an option and its value from the arguments and returns the value as a number of days
+fn days(args: &mut Vec<String>, name: &str) -> Result<Option<Duration>, String> {
+    Ok(parsed(args, name)?.map(Duration::days))
+}
+
+// Parses the arguments of the application, the first argument is the application itself.
+// A first argument that is not a command is the file of the process command.
+pub fn parse(args: Vec<String>) -> Result<Cli, String> {
+    let mut args: Vec<String> = args.into_iter().skip(1).collect();
+
+    // -h, --help
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        let command = args
+            .iter()
+            .find(|arg| COMMANDS.iter().any(|(name, ..)| name == arg))
+            .cloned();
+        return Ok(Cli::new(Command::Help(command)));
+    }
+
+    // -V, --version
+    if args.iter().any(|arg| arg == "-V" || arg == "--version") {
+        return Ok(Cli::new(Command::Version));
+    }
+
+    // options
+    let mut cli = Cli::new(Command::Validate);
+    let input: Option<Format> = parsed(&mut args, "--input-format")?;
+    let format = option(&mut args, "--format")?;
+    let lenient = flag(&mut args, "--lenient");
+    let max_errors = parsed(&mut args, "--max-errors")?;
+    cli.fees = option(&mut args, "--fees")?;
+    cli.limits = option(&mut args, "--limits")?;
+    cli.risk = option(&mut args, "--risk")?;
+    cli.flagged = option(&mut args, "--flagged")?;
+    cli.rates = option(&mut args, "--rates")?;
+    cli.base = parsed(&mut args, "--base")?;
+    if let Some(threads) = parsed(&mut args, "--threads")? {
+        cli.threads = threads;
+    }
+    cli.config.dispute_window = days(&mut args, "--dispute-window")?;
+    cli.config.dispute_expiry = days(&mut args, "--dispute-expiry")?;
+    cli.config.clearing_rows = parsed(&mut args, "--clearing-rows")?;
+    cli.config.clearing_period = days(&mut args, "--clearing-days")?;
+    if let Some(policy) = parsed(&mut args, "--withdrawal-disputes")? {
+        cli.config.withdrawal_dispute = policy;
+    }
+    if let Some(rule) = parsed(&mut args, "--redispute")? {
+        cli.config.redispute = rule;
+    }
+    let prefix = option(&mut args, "--prefix")?;
+    let start = parsed(&mut args, "--start")?;
+    let date = parsed(&mut args, "--date")?;
+    let terms = parsed(&mut args, "--terms")?;
+    let template = option(&mut args, "--template")?;
+    let invoicing = prefix.is_some()
+        || start.is_some()
+        || date.is_some()
+        || terms.is_some()
+        || template.is_some();
+
+    if let Some(arg) = args.iter().find(|arg| arg.starts_with("--")) {
+        return Err(format!("Error: Unknown option {}", arg));
+    }
+
+    // command and its arguments
+    let mut arguments = args.into_iter();
+    let name = match arguments.next() {
+        None => return Err("Error: No command or file path provided".to_string()),
+        Some(name) => name,
+    };
+    let (name, mut arguments): (String, Vec<String>) =
+        match COMMANDS.iter().any(|(command, ..)| *command == name) {
+            true => (name, arguments.collect()),
+            false => (
+                "process".to_string(),
+                std::iter::once(name).chain(arguments).collect(),
+            ),
+        };
+
+    // serve and listen take an address, help a command and the rest a file first
+    let reads = !matches!(name.as_str(), "serve" | "listen" | "help");
+    if reads {
+        if arguments.is_empty() {
+            return Err(format!(
+                "Error: No file path provided for the {} command",
+                name
+            ));
+        }
+        cli.path = Some(arguments.remove(0));
+    }
+    let mut arguments = arguments.into_iter();
+
+    cli.command = match (name.as_str(), format.as_deref()) {
+        ("process", format) => Command::Process(format.unwrap_or("csv").parse()?),
+        ("statement", format) => Command::Statement(format.unwrap_or("csv").parse()?),
+        ("invoice", format) => {
+            let mut options = invoice::Options {
+                format: format.unwrap_or("html").parse()?,
+                template,
+                ..Default::default()
+            };
+            if let Some(prefix) = prefix {
+                options.prefix = prefix;
+            }
+            if let Some(start) = start {
+                options.start = start;
+            }
+            if let Some(date) = date {
+                options.date = date;
+            }
+            if let Some(terms) = terms {
+                options.terms = terms;
+            }
+            Command::Invoice(
+                arguments.next().unwrap_or_else(|| "invoices".to_string()),
+                options,
+            )
+        }
+        (name, Some(_)) => {
+            return Err(format!("Error: The {} command has no --format", name));
+        }
+        ("validate", None) => Command::Validate,
+        ("audit", None) => Command::Audit,
+        ("lifecycle", None) => match arguments.next().map(|tx| tx.parse()) {
+            None => return Err("Error: No tx provided".to_string()),
+            Some(Err(err)) => return Err(format!("Error: Invalid tx, {}", err)),
+            Some(Ok(tx)) => Command::Lifecycle(tx),
+        },
+        ("rejections", None) => Command::Rejections,
+        ("replay", None) => Command::Replay,
+        ("serve", None) => Command::Serve(
+            arguments
+                .next()
+                .unwrap_or_else(|| "127.0.0.1:8080".to_string()),
+        ),
+        ("listen", None) => Command::Listen(
+            arguments
+                .next()
+                .unwrap_or_else(|| "127.0.0.1:9000".to_string()),
+        ),
+        (_, None) => Command::Help(arguments.next()),
+    };
+
+    if let Some(arg) = arguments.next() {
+        return Err(format!("Error: Unexpected argument {}", arg));
+    }
+
+    // --prefix, --start, --date, --terms, --template
+    if name != "invoice" && invoicing {
+        return Err(format!(
+            "Error: The {} command has no invoice options",
+            name
+        ));
+    }
+
+    // replay reads json and csv lines alike
+    match (input, reads && cli.command != Command::Replay) {
+        (Some(_), false) => {
+            return Err(format!("Error: The {} command has no --input-format", name))
+        }
+        (input, _) => cli.input = input.unwrap_or(Format::Csv),
+    }
+
+    // --lenient, --max-errors <n>
+    if lenient || max_errors.is_some() || cli.command == Command::Validate {
+        if !reads || cli.command == Command::Replay {
+            return Err(format!("Error: The {} command has no --lenient", name));
+        }
+        cli.mode = Mode::Lenient(max_errors);
+    }
+
+    Ok(cli)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        std::iter::once("invoice")
+            .chain(line.split_whitespace())
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let cli = parse(args("sample.csv --threads 4 --fees fees.csv")).expect("Failed to parse");
+        assert_eq!(cli.command, Command::Process(Format::Csv));
+        assert_eq!(cli.path, Some("sample.csv".to_string()));
+        assert_eq!(cli.fees, Some("fees.csv".to_string()));
+        assert_eq!(cli.threads, 4);
+
+        let cli = parse(args(
+            "statement sample.csv --format text --dispute-window 30",
+        ))
+        .expect("Failed to parse");
+        assert_eq!(cli.command, Command::Statement(statement::Format::Text));
+        assert_eq!(cli.config.dispute_window, Some(Duration::days(30)));
+
+        let cli = parse(args("invoice sample.csv out")).expect("Failed to parse");
+        match cli.command {
+            Command::Invoice(dir, options) => {
+                assert_eq!(dir, "out");
+                assert_eq!(options.format, invoice::Format::Html);
+                assert_eq!(options.prefix, "INV-");
+                assert_eq!(options.template, None);
+            }
+            command => panic!("Unexpected command {:?}", command),
+        }
+
+        let cli = parse(args(
+            "invoice sample.csv --format text --prefix ACME- --start 7 --date 2024-01-31 --terms 14 --template invoice.txt",
+        ))
+        .expect("Failed to parse");
+        assert_eq!(
+            cli.command,
+            Command::Invoice(
+                "invoices".to_string(),
+                invoice::Options {
+                    prefix: "ACME-".to_string(),
+                    start: 7,
+                    date: chrono::NaiveDate::from_ymd_opt(2024, 1, 31).unwrap(),
+                    terms: 14,
+                    format: invoice::Format::Text,
+                    template: Some("invoice.txt".to_string()),
+                }
+            )
+        );
+
+        let cli = parse(args("process --input-format json records.json")).expect("Failed to parse");
+        assert_eq!(cli.input, Format::Json);
+        assert_eq!(cli.path, Some("records.json".to_string()));
+
+        let cli = parse(args("audit sample.csv --max-errors 5")).expect("Failed to parse");
+        assert_eq!(cli.mode, Mode::Lenient(Some(5)));
+        let cli = parse(args("validate sample.csv")).expect("Failed to parse");
+        assert_eq!(cli.mode, Mode::Lenient(None));
+        let cli = parse(args("sample.csv --lenient")).expect("Failed to parse");
+        assert_eq!(cli.mode, Mode::Lenient(None));
+
+        let cli = parse(args("serve")).expect("Failed to parse");
+        assert_eq!(cli.command, Command::Serve("127.0.0.1:8080".to_string()));
+        assert_eq!(cli.path, None);
+    }
+
+    #[test]
+    fn test_parse_help() {
+        assert_eq!(
+            parse(args("--help")).map(|cli| cli.command),
+            Ok(Command::Help(None))
+        );
+        assert_eq!(
+            parse(args("audit -h")).map(|cli| cli.command),
+            Ok(Command::Help(Some("audit".to_string())))
+        );
+        assert_eq!(
+            parse(args("help replay")).map(|cli| cli.command),
+            Ok(Command::Help(Some("replay".to_string())))
+        );
+        assert_eq!(
+            parse(args("sample.csv -V")).map(|cli| cli.command),
+            Ok(Command::Version)
+        );
+
+        assert!(help(None).contains("validate <file>"));
+        assert!(help(Some("replay")).starts_with("Usage: invoice replay <file>"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for line in [
+            "",
+            "audit",
+            "lifecycle sample.csv",
+            "lifecycle sample.csv x",
+            "audit sample.csv --format text",
+            "statement sample.csv --format html",
+            "serve --input-format json",
+            "listen --lenient",
+            "sample.csv --max-errors x",
+            "sample.csv --threads",
+            "sample.csv --thread 4",
+            "sample.csv other.csv",
+            "invoice sample.csv --date 31-01-2024",
+            "invoice sample.csv --start x",
+            "statement sample.csv --prefix ACME-",
+        ] {
+            assert!(parse(args(line)).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_exit() {
+        let err: Box<dyn Error> = io::Error::new(io::ErrorKind::NotFound, "missing").into();
+        assert_eq!(Exit::of(&*err), Exit::Io);
+
+        let err: Box<dyn Error> = "Error: Empty line".into();
+        assert_eq!(Exit::of(&*err), Exit::Parse);
+
+        let err = crate::csv_reader::CSV::from_reader("type,client,tx\nx,1,1\n".as_bytes())
+            .expect_err("Failed to reject record");
+        assert_eq!(Exit::of(&*err), Exit::Parse);
+    }
+}
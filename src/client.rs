@@ -3,12 +3,25 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::csv_reader::Record;
+use crate::currency::Currency;
 
-// Create a type for easier fn definitions
-pub type ClientMap = HashMap<u16, Client>;
+// Create a type for easier fn definitions, a client has balances in every currency it uses
+pub type ClientKey = (u16, Option<Currency>);
+pub type ClientMap = HashMap<ClientKey, Client>;
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy)]
 pub struct Client {
+    pub client: u16,
+    pub currency: Option<Currency>,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+// Balances of a client without the currency, the output of files that do not use currencies
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct Balances {
     pub client: u16,
     pub available: f64,
     pub held: f64,
@@ -17,23 +30,39 @@ pub struct Client {
 }
 
 impl Client {
-    // Returns the client from the given record. If client does not exist, it will create one with default values.
-    pub fn find(record: &Record, clients: &ClientMap) -> Self {
+    // Returns the client from the given record with its balances in the given currency.
+    // If client does not exist, it will create one with default values.
+    #[allow(clippy::clone_on_copy)]
+    pub fn find(record: &Record, currency: Option<Currency>, clients: &ClientMap) -> Self {
         // return the client if we already have them
-        if clients.contains_key(&record.client) {
-            return clients
-                .get(&record.client)
-                .expect("Failed to get client")
-                .clone();
+        let key = (record.client, currency);
+        if clients.contains_key(&key) {
+            return clients.get(&key).expect("Failed to get client").clone();
         }
 
         // otherwise create one
-        Client::from(record)
+        Client {
+            currency,
+            ..Client::from(record)
+        }
+    }
+}
+
+impl From<Client> for Balances {
+    fn from(client: Client) -> Self {
+        Self {
+            client: client.client,
+            available: client.available,
+            held: client.held,
+            total: client.total,
+            locked: client.locked,
+        }
     }
 }
 
 // Create a new Client from a Record
 impl From<&Record> for Client {
+    #[allow(clippy::redundant_field_names)]
     fn from(record: &Record) -> Self {
         // generate an id if necessary
         let mut client: u16 = record.client;
@@ -44,6 +73,7 @@ impl From<&Record> for Client {
 
         Self {
             client: client,
+            currency: record.currency,
             ..Default::default()
         }
     }
@@ -54,6 +84,7 @@ impl Default for Client {
     fn default() -> Client {
         Client {
             client: 0,
+            currency: None,
             available: 0.0,
             held: 0.0,
             total: 0.0,
@@ -63,6 +94,7 @@ impl Default for Client {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use crate::csv_reader::Type;
@@ -76,9 +108,15 @@ mod tests {
             tx: 1,
             amount: Some(1.0),
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
 
-        let client = Client::find(&record, &clients);
+        let client = Client::find(&record, None, &clients);
 
         assert_ne!(client.client, 0);
         assert_eq!(client.available, 0.0);
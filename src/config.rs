@@ -0,0 +1,178 @@
+use chrono::Duration;
+use std::str::FromStr;
+
+use crate::csv_reader::Type;
+use crate::fee::FeeSchedule;
+use crate::fx::Rates;
+use crate::ledger::Account;
+use crate::limits::Limits;
+use crate::risk::Risk;
+
+// How a dispute on a withdrawal moves funds, for a withdrawal of amount A:
+//
+// Provisional: the withdrawn amount is held until the dispute is settled
+//   dispute    external -> held        available +0, held +A, total +A
+//   resolve    held -> external        available +0, held -A, total -A
+//   chargeback held -> available       available +A, held -A, total +0
+//
+// Disallow: disputes on withdrawals are ignored, as are their resolves and chargebacks
+//
+// ReverseOnChargeback: nothing moves until the withdrawal is charged back
+//   dispute    nothing
+//   resolve    nothing
+//   chargeback external -> available   available +A, held +0, total +A
+//
+// HoldFromAvailable: the disputed amount is held from the funds the client has left
+//   dispute    available -> held       available -A, held +A, total +0
+//   resolve    held -> available       available +A, held -A, total +0
+//   chargeback held -> available,
+//              external -> available   available +2A, held -A, total +A
+//
+// A chargeback locks the client in every policy except Disallow.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WithdrawalDispute {
+    #[default]
+    Provisional,
+    Disallow,
+    ReverseOnChargeback,
+    HoldFromAvailable,
+}
+
+impl FromStr for WithdrawalDispute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "provisional" => Ok(WithdrawalDispute::Provisional),
+            "disallow" => Ok(WithdrawalDispute::Disallow),
+            "reverse-on-chargeback" => Ok(WithdrawalDispute::ReverseOnChargeback),
+            "hold-funds-from-available" => Ok(WithdrawalDispute::HoldFromAvailable),
+            _ => Err(format!("Error: Unknown withdrawal dispute policy {}", s)),
+        }
+    }
+}
+
+impl WithdrawalDispute {
+    // Returns the accounts funds move between for a dispute, resolve or chargeback of a withdrawal
+    pub fn postings(&self, r#type: Type) -> &'static [(Account, Account)] {
+        use Account::*;
+
+        match (self, r#type) {
+            (WithdrawalDispute::Provisional, Type::DISPUTE) => &[(External, Held)],
+            (WithdrawalDispute::Provisional, Type::RESOLVE) => &[(Held, External)],
+            (WithdrawalDispute::Provisional, Type::CHARGEBACK) => &[(Held, Available)],
+            (WithdrawalDispute::ReverseOnChargeback, Type::CHARGEBACK) => &[(External, Available)],
+            (WithdrawalDispute::HoldFromAvailable, Type::DISPUTE) => &[(Available, Held)],
+            (WithdrawalDispute::HoldFromAvailable, Type::RESOLVE) => &[(Held, Available)],
+            (WithdrawalDispute::HoldFromAvailable, Type::CHARGEBACK) => {
+                &[(Held, Available), (External, Available)]
+            }
+            _ => &[],
+        }
+    }
+}
+
+// Whether a tx may be disputed again after its dispute was resolved.
+// Disputing more of a tx while it is under dispute is not a re-dispute.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Redispute {
+    #[default]
+    Allow,
+    Never,
+    // the number of times a resolved tx may be disputed again
+    Limit(usize),
+}
+
+impl FromStr for Redispute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Redispute::Allow),
+            "never" => Ok(Redispute::Never),
+            _ => match s.parse() {
+                Ok(limit) => Ok(Redispute::Limit(limit)),
+                Err(_) => Err(format!("Error: Unknown re-dispute rule {}", s)),
+            },
+        }
+    }
+}
+
+impl Redispute {
+    // Returns true if a tx that was resolved the given number of times may be disputed again
+    pub fn allows(&self, resolved: usize) -> bool {
+        match self {
+            Redispute::Allow => true,
+            Redispute::Never => resolved == 0,
+            Redispute::Limit(limit) => resolved <= *limit,
+        }
+    }
+}
+
+// Configuration of the engine
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub fees: FeeSchedule,
+    pub rates: Rates,
+    pub limits: Limits,
+    pub risk: Risk,
+    pub withdrawal_dispute: WithdrawalDispute,
+    pub redispute: Redispute,
+    // disputes later than this after the original transaction are ignored
+    pub dispute_window: Option<Duration>,
+    // disputes open longer than this are resolved
+    pub dispute_expiry: Option<Duration>,
+    // deposits clear after this many subsequent rows
+    pub clearing_rows: Option<usize>,
+    // deposits clear this long after their timestamp
+    pub clearing_period: Option<Duration>,
+}
+
+impl Config {
+    // Returns true if deposits are pending until they clear
+    pub fn clearing(&self) -> bool {
+        self.clearing_rows.is_some() || self.clearing_period.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_withdrawal_dispute() {
+        assert_eq!(
+            "hold-funds-from-available".parse::<WithdrawalDispute>(),
+            Ok(WithdrawalDispute::HoldFromAvailable)
+        );
+        assert!("hold".parse::<WithdrawalDispute>().is_err());
+    }
+
+    #[test]
+    fn test_postings() {
+        let policy = WithdrawalDispute::Disallow;
+
+        assert!(policy.postings(Type::DISPUTE).is_empty());
+        assert!(policy.postings(Type::CHARGEBACK).is_empty());
+        assert_eq!(
+            WithdrawalDispute::ReverseOnChargeback.postings(Type::CHARGEBACK),
+            &[(Account::External, Account::Available)]
+        );
+    }
+
+    #[test]
+    fn test_parse_redispute() {
+        assert_eq!("never".parse::<Redispute>(), Ok(Redispute::Never));
+        assert_eq!("2".parse::<Redispute>(), Ok(Redispute::Limit(2)));
+        assert!("-1".parse::<Redispute>().is_err());
+    }
+
+    #[test]
+    fn test_redispute_allows() {
+        assert!(Redispute::Never.allows(0));
+        assert!(!Redispute::Never.allows(1));
+        assert!(Redispute::Limit(1).allows(1));
+        assert!(!Redispute::Limit(1).allows(2));
+        assert!(Redispute::Allow.allows(5));
+    }
+}
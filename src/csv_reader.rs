@@ -1,17 +1,25 @@
+use chrono::{DateTime, Utc};
 use csv::{ReaderBuilder, Trim};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
-use std::io;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
 
-use crate::client::ClientMap;
+use crate::client::{Balances, ClientMap};
+use crate::currency::Currency;
+use crate::diagnostic::Malformed;
+
+// A line of a file with its record, or why it is not one
+type Line = (usize, Result<Record, Malformed>);
 
 // Hashmap for fast transaction lookup
 pub type RecordMap = HashMap<u32, Record>;
 pub type RecordVec = Vec<Record>;
 
 // Spec of the input csv file.
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Record {
     pub r#type: Type,
     pub client: u16,
@@ -19,9 +27,43 @@ pub struct Record {
     pub amount: Option<f64>,
     // additional field
     pub status: Option<Status>,
+    // optional column, RFC 3339 (2021-01-01T00:00:00Z)
+    pub timestamp: Option<DateTime<Utc>>,
+    // optional column, disputes, resolves and chargebacks without one are in the currency of the tx
+    pub currency: Option<Currency>,
+    // optional column, the currency a convert moves funds to
+    pub to: Option<Currency>,
+    // optional column, the client a transfer moves funds to
+    pub recipient: Option<u16>,
+    // optional column, a record submitted again with the same key is not applied again
+    pub idempotency_key: Option<String>,
+    // additional field, row of the record in the csv starting at 1
+    #[serde(skip)]
+    pub row: usize,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+// A record without any of the optional columns, the tests set the fields they need on top of it
+#[cfg(test)]
+impl Record {
+    pub fn new(r#type: Type, client: u16, tx: u32, amount: Option<f64>) -> Record {
+        Record {
+            r#type,
+            client,
+            tx,
+            amount,
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Type {
     #[serde(rename = "deposit")]
     DEPOSIT,
@@ -33,8 +75,22 @@ pub enum Type {
     RESOLVE,
     #[serde(rename = "chargeback")]
     CHARGEBACK,
+    #[serde(rename = "convert")]
+    CONVERT,
+    #[serde(rename = "transfer")]
+    TRANSFER,
+    // the review of a deposit or withdrawal held by the risk rules, it is applied
+    #[serde(rename = "release")]
+    RELEASE,
+    // the review of a deposit or withdrawal held by the risk rules, it is reversed
+    #[serde(rename = "reject")]
+    REJECT,
+    // a pending deposit that cleared, made by the engine and never read from a file
+    #[serde(rename = "clear", skip_deserializing)]
+    CLEAR,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
 pub enum Status {
     NONE,
@@ -43,103 +99,463 @@ pub enum Status {
     CHARGEBACKED,
 }
 
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Type::DEPOSIT => "deposit",
+            Type::WITHDRAWAL => "withdrawal",
+            Type::DISPUTE => "dispute",
+            Type::RESOLVE => "resolve",
+            Type::CHARGEBACK => "chargeback",
+            Type::CONVERT => "convert",
+            Type::TRANSFER => "transfer",
+            Type::RELEASE => "release",
+            Type::REJECT => "reject",
+            Type::CLEAR => "clear",
+        };
+
+        f.pad(name)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Status::NONE => "",
+            Status::DISPUTED => "disputed",
+            Status::RESOLVED => "resolved",
+            Status::CHARGEBACKED => "chargebacked",
+        };
+
+        f.pad(name)
+    }
+}
+
+// Formats of the records read and of the balances written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    // a json object on every line
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Error: Unknown format {}", s)),
+        }
+    }
+}
+
+// Adds a record to the lookup of the transactions it may refer to,
+// a dispute marks the tx it refers to as disputed
+#[allow(clippy::unnecessary_unwrap)]
+pub fn index(records_map: &mut RecordMap, record: Record) {
+    match record.r#type {
+        // the first record of a tx is kept, a later one is not applied
+        Type::DEPOSIT | Type::WITHDRAWAL | Type::TRANSFER
+            if records_map.contains_key(&record.tx) =>
+        {
+            None
+        }
+        Type::DEPOSIT => records_map.insert(record.tx, record),
+        Type::WITHDRAWAL => records_map.insert(record.tx, record),
+        Type::TRANSFER => records_map.insert(record.tx, record),
+        Type::DISPUTE => {
+            let orignal_record = records_map.get(&record.tx);
+
+            if orignal_record.is_some() {
+                let mut cloned_record = orignal_record.expect("Failed to get record").clone();
+                cloned_record.status = Some(Status::DISPUTED);
+                records_map.insert(cloned_record.tx, cloned_record.clone());
+            }
+
+            None
+        }
+        Type::RESOLVE => None,
+        Type::CHARGEBACK => None,
+        // conversions can not be disputed
+        Type::CONVERT => None,
+        // clears are never read from a file
+        Type::CLEAR => None,
+        Type::RELEASE => None,
+        Type::REJECT => None,
+    };
+}
+
+// How a load treats a line that is not a record
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Mode {
+    // the first one fails the load
+    #[default]
+    Strict,
+    // skipped and kept with the records, the load fails once there are more than the maximum
+    Lenient(Option<usize>),
+}
+
 // Contains a vector and hashmap of records
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, Default)]
 pub struct CSV {
     pub records_vec: RecordVec,
     pub records_map: RecordMap,
+    // line of every record in the file, empty for records that were not read from a file
+    pub lines: Vec<usize>,
+    // lines skipped by a lenient load
+    pub malformed: Vec<Malformed>,
+}
+
+// Returns the line of the text, lines start at 1 and a line outside of the text is empty
+fn raw(text: &str, line: usize) -> &str {
+    text.lines().nth(line.wrapping_sub(1)).unwrap_or_default()
 }
 
 impl CSV {
-    // Loads the provided csv file and returns a CSV
-    pub fn load(args: Vec<String>) -> Result<Self, Box<dyn Error>> {
+    // Returns the line of the file the record of a row was read from, the row itself if it has none
+    pub fn line(&self, row: usize) -> usize {
+        row.checked_sub(1)
+            .and_then(|i| self.lines.get(i))
+            .copied()
+            .unwrap_or(row)
+    }
+
+    // Loads the csv file at the path and returns a CSV
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        CSV::open(path, Format::Csv, Mode::Strict)
+    }
+
+    // Loads the file at the path in the given format and returns a CSV
+    pub fn open(path: &str, format: Format, mode: Mode) -> Result<Self, Box<dyn Error>> {
         // check for file path
-        if args.len() < 2 {
+        if path.is_empty() {
             return Err("Error: No file path provided")?;
         }
 
-        // create vector and hashmap
-        let mut records_vec = vec![];
-        let mut records_map: RecordMap = HashMap::new();
+        let text = std::fs::read_to_string(path)?;
+
+        CSV::parse(&text, format, mode)
+    }
+
+    // Loads the records of a csv with headers from the reader
+    pub fn from_reader<R: io::Read>(mut reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+
+        CSV::parse(&text, Format::Csv, Mode::Strict)
+    }
+
+    // Loads the records of a reader with a json record on every line, empty lines are skipped
+    pub fn from_json_reader<R: io::Read>(mut reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+
+        CSV::parse(&text, Format::Json, Mode::Strict)
+    }
+
+    // Reads the records of the text with their line, a line that is not a record has why
+    fn read(text: &str, format: Format) -> Result<Vec<Line>, Box<dyn Error>> {
+        let raw = |line: usize| raw(text, line);
+
+        match format {
+            Format::Csv => {
+                // read csv with options
+                // FIXME: determine headers manually
+                // as it will allow us to take both file types
+                let mut rdr = ReaderBuilder::new()
+                    .has_headers(true)
+                    .trim(Trim::All)
+                    .from_reader(text.as_bytes());
+                let headers = rdr.headers()?.clone();
+
+                let records = rdr
+                    .records()
+                    .map(|result| match result {
+                        Err(err) => {
+                            let line = err.position().map_or(0, |position| position.line());
+                            let line = line as usize;
+                            let malformed = Malformed::csv(line, raw(line), &headers, None, &err);
+                            (line, Err(malformed))
+                        }
+                        Ok(row) => {
+                            let line = row.position().map_or(0, |position| position.line());
+                            let line = line as usize;
+                            let record = row.deserialize(Some(&headers)).map_err(|err| {
+                                Malformed::csv(line, raw(line), &headers, Some(&row), &err)
+                            });
+                            (line, record)
+                        }
+                    })
+                    .collect();
+
+                Ok(records)
+            }
+            Format::Json => Ok(text
+                .lines()
+                .enumerate()
+                .filter(|(_i, line)| !line.trim().is_empty())
+                .map(|(i, line)| {
+                    let record = serde_json::from_str(line)
+                        .map_err(|err| Malformed::json(i + 1, line, &err));
+                    (i + 1, record)
+                })
+                .collect()),
+        }
+    }
 
-        // read csv with options
-        // FIXME: determine headers manually
-        // as it will allow us to take both file types
-        let mut rdr = ReaderBuilder::new()
-            .has_headers(true)
-            .trim(Trim::All)
-            .from_path(&args[1])?;
+    // Numbers and indexes the records of the text. A strict load returns the first line that is
+    // not a record, a lenient load skips it.
+    pub fn parse(text: &str, format: Format, mode: Mode) -> Result<Self, Box<dyn Error>> {
+        let mut csv = CSV::default();
 
         // convert data into Record and add to vector
-        for result in rdr.deserialize() {
-            let record: Record = result?;
-            records_vec.push(record.clone());
-
-            // update record with disputed field
-            match record.r#type {
-                Type::DEPOSIT => records_map.insert(record.tx, record),
-                Type::WITHDRAWAL => records_map.insert(record.tx, record),
-                Type::DISPUTE => {
-                    let orignal_record = records_map.get(&record.tx);
-
-                    if orignal_record.is_some() {
-                        let mut cloned_record =
-                            orignal_record.expect("Failed to get record").clone();
-                        cloned_record.status = Some(Status::DISPUTED);
-                        records_map.insert(cloned_record.tx, cloned_record.clone());
-                    }
+        let mut last: Option<DateTime<Utc>> = None;
+        for (line, result) in CSV::read(text, format)? {
+            let malformed = match result {
+                Err(malformed) => malformed,
+                // records with a timestamp must be in chronological order
+                Ok(record)
+                    if record
+                        .timestamp
+                        .is_some_and(|t| last.is_some_and(|l| t < l)) =>
+                {
+                    Malformed::new(
+                        line,
+                        raw(text, line),
+                        "The record is earlier than the previous record".to_string(),
+                    )
+                }
+                Ok(mut record) => {
+                    last = record.timestamp.or(last);
+                    record.row = csv.records_vec.len() + 1;
+
+                    csv.records_vec.push(record.clone());
+                    csv.lines.push(line);
 
-                    None
+                    index(&mut csv.records_map, record);
+                    continue;
                 }
-                Type::RESOLVE => None,
-                Type::CHARGEBACK => None,
             };
+
+            let max = match mode {
+                Mode::Strict => return Err(malformed.into()),
+                Mode::Lenient(max) => max,
+            };
+            csv.malformed.push(malformed);
+            if let Some(max) = max.filter(|max| csv.malformed.len() > *max) {
+                return Err(format!(
+                    "Error: More than {} malformed lines, the last at line {}",
+                    max, line
+                ))?;
+            }
         }
 
         // return CSV
-        Ok(CSV {
-            records_vec,
-            records_map,
-        })
+        Ok(csv)
     }
 
-    // Writes the provided clients as csv to the stdout
-    pub fn output(clients: ClientMap) -> Result<(), Box<dyn Error>> {
-        let mut wtr = csv::Writer::from_writer(io::stdout());
+    // Writes the provided clients to the stdout, as csv or as a json client on every line
+    pub fn output(clients: ClientMap, format: Format) -> Result<(), Box<dyn Error>> {
+        CSV::write(clients, format, io::stdout().lock())
+    }
 
-        for (_id, client) in clients {
-            wtr.serialize(client)?;
-        }
+    // Writes the provided clients to the writer, the csv only has a currency column
+    // if one of the clients has a currency
+    pub fn write<W: Write>(
+        clients: ClientMap,
+        format: Format,
+        mut writer: W,
+    ) -> Result<(), Box<dyn Error>> {
+        match format {
+            Format::Csv => {
+                let currencies = clients.values().any(|client| client.currency.is_some());
+                let mut wtr = csv::Writer::from_writer(writer);
+
+                for (_id, client) in clients {
+                    match currencies {
+                        true => wtr.serialize(client)?,
+                        false => wtr.serialize(Balances::from(client))?,
+                    }
+                }
 
-        wtr.flush()?;
+                wtr.flush()?;
+            }
+            Format::Json => {
+                for (_id, client) in clients {
+                    serde_json::to_writer(&mut writer, &client)?;
+                    writeln!(writer)?;
+                }
+
+                writer.flush()?;
+            }
+        }
 
         Ok(())
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+    use crate::client::Client;
 
     #[test]
     fn test_empty_argument() {
-        let csv = CSV::load(vec!["".to_string()]);
+        let csv = CSV::load("");
 
         assert_eq!(csv.is_err(), true);
     }
 
     #[test]
     fn test_non_existant_file() {
-        let csv = CSV::load(vec!["main".to_string(), "no_file.csv".to_string()]);
+        let csv = CSV::load("no_file.csv");
 
         assert_eq!(csv.is_err(), true);
     }
 
     #[test]
     fn test_load() {
-        let csv = CSV::load(vec!["main".to_string(), "sample.csv".to_string()])
-            .expect("Failed load csv file");
+        let csv = CSV::load("sample.csv").expect("Failed load csv file");
 
         assert_eq!(csv.records_vec.len(), 17);
+        assert_eq!(csv.records_vec[0].row, 1);
+        assert_eq!(csv.records_vec[16].row, 17);
+    }
+
+    #[test]
+    fn test_load_timestamps() {
+        let path = std::env::temp_dir().join("invoice_test_timestamps.csv");
+        std::fs::write(
+            &path,
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,1,1.0,2021-01-01T00:00:00Z\n\
+             deposit,1,2,1.0,\n\
+             dispute,1,1,,2021-02-01T00:00:00Z\n",
+        )
+        .expect("Failed to write csv file");
+
+        let csv = CSV::load(&path.display().to_string()).expect("Failed load csv file");
+
+        assert_eq!(csv.records_vec.len(), 3);
+        assert!(csv.records_vec[1].timestamp.is_none());
+        assert_eq!(
+            csv.records_vec[2].timestamp.map(|t| t.to_rfc3339()),
+            Some("2021-02-01T00:00:00+00:00".to_string())
+        );
+
+        std::fs::remove_file(&path).expect("Failed to remove csv file");
+    }
+
+    #[test]
+    fn test_load_currencies() {
+        let path = std::env::temp_dir().join("invoice_test_currencies.csv");
+        std::fs::write(
+            &path,
+            "type,client,tx,amount,currency\n\
+             deposit,1,1,1.0,eur\n\
+             deposit,1,2,1.0,\n\
+             dispute,1,1,,\n",
+        )
+        .expect("Failed to write csv file");
+
+        let csv = CSV::load(&path.display().to_string()).expect("Failed load csv file");
+
+        assert_eq!(csv.records_vec[0].currency, "EUR".parse().ok());
+        assert!(csv.records_vec[1].currency.is_none());
+        assert!(csv.records_vec[2].currency.is_none());
+
+        std::fs::remove_file(&path).expect("Failed to remove csv file");
+    }
+
+    #[test]
+    fn test_load_not_chronological() {
+        let path = std::env::temp_dir().join("invoice_test_not_chronological.csv");
+        std::fs::write(
+            &path,
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,1,1.0,2021-02-01T00:00:00Z\n\
+             deposit,1,2,1.0,2021-01-01T00:00:00Z\n",
+        )
+        .expect("Failed to write csv file");
+
+        let csv = CSV::load(&path.display().to_string());
+
+        assert!(csv.is_err());
+
+        std::fs::remove_file(&path).expect("Failed to remove csv file");
+    }
+
+    #[test]
+    fn test_write() {
+        let mut clients = ClientMap::new();
+        let client = Client {
+            client: 1,
+            ..Default::default()
+        };
+        clients.insert((client.client, client.currency), client);
+
+        let mut output = vec![];
+        CSV::write(clients.clone(), Format::Csv, &mut output).expect("Failed to write clients");
+        let output = String::from_utf8(output).expect("Failed to read clients");
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n1,0.0,0.0,0.0,false\n"
+        );
+
+        // the currency column is written once a client has a currency
+        let eur: Currency = "EUR".parse().expect("Failed to parse currency");
+        let client = Client {
+            currency: Some(eur),
+            ..client
+        };
+        clients.insert((client.client, client.currency), client);
+        let mut output = vec![];
+        CSV::write(clients, Format::Csv, &mut output).expect("Failed to write clients");
+        let output = String::from_utf8(output).expect("Failed to read clients");
+        assert!(output.starts_with("client,currency,available,held,total,locked\n"));
+    }
+
+    #[test]
+    fn test_from_json_reader() {
+        let csv = CSV::from_json_reader(
+            "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 2.0}\n\
+             \n\
+             {\"type\": \"dispute\", \"client\": 1, \"tx\": 1}\n"
+                .as_bytes(),
+        )
+        .expect("Failed to read json records");
+
+        assert_eq!(csv.records_vec.len(), 2);
+        assert_eq!(csv.records_vec[1].row, 2);
+        assert_eq!(csv.records_map[&1].status, Some(Status::DISPUTED));
+        assert!(CSV::from_json_reader("{\"type\": \"deposit\"}".as_bytes()).is_err());
+        assert_eq!("json".parse(), Ok(Format::Json));
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        let text = "type,client,tx,amount\n\
+                    deposit,1,1,1.0\n\
+                    deposit,1,2,1,000.0\n\
+                    depositt,1,3,1.0\n\
+                    withdrawal,1,4,0.5\n";
+
+        assert!(CSV::parse(text, Format::Csv, Mode::Strict).is_err());
+
+        let csv = CSV::parse(text, Format::Csv, Mode::Lenient(None)).expect("Failed to load csv");
+        assert_eq!(csv.records_vec.len(), 2);
+        assert_eq!(csv.records_vec[1].row, 2);
+        assert_eq!(csv.lines, vec![2, 5]);
+        assert_eq!(csv.malformed.len(), 2);
+        assert_eq!(csv.malformed[0].line, 3);
+        assert_eq!(csv.malformed[0].raw, "deposit,1,2,1,000.0");
+        assert_eq!(csv.malformed[1].raw, "depositt,1,3,1.0");
+
+        assert!(CSV::parse(text, Format::Csv, Mode::Lenient(Some(1))).is_err());
+        assert!(CSV::parse(text, Format::Csv, Mode::Lenient(Some(2))).is_ok());
     }
 }
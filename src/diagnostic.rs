@@ -0,0 +1,370 @@
+use chrono::{DateTime, Utc};
+use csv::{DeserializeErrorKind, ErrorKind, StringRecord};
+use std::error::Error;
+use std::fmt;
+use std::num::IntErrorKind;
+use std::ops::Range;
+
+use crate::csv_reader::{Status, Type};
+use crate::currency::Currency;
+
+// Separators people put between the thousands of an amount
+const SEPARATORS: [char; 4] = [',', '\'', ' ', '_'];
+
+// A line that could not be read as a record, with where and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct Malformed {
+    pub line: usize,
+    // the line as it is in the file
+    pub raw: String,
+    // column of the field that could not be read
+    pub field: Option<String>,
+    // the field as it is in the line
+    pub value: Option<String>,
+    // what is wrong with the line
+    pub error: String,
+    // bytes of the line the error points at
+    pub span: Option<Range<usize>>,
+}
+
+impl fmt::Display for Malformed {
+    // Writes the error with the line under it and the field marked
+    //
+    // Error: Unknown type `depositt`, expected deposit, ...
+    //  --> line 3, field `type`
+    //   |
+    // 3 | depositt,1,2,1.0
+    //   | ^^^^^^^^
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        write!(f, "Error: {}\n{}--> line {}", self.error, gutter, self.line)?;
+        if let Some(field) = &self.field {
+            write!(f, ", field `{}`", field)?;
+        }
+        write!(f, "\n{} |\n{} | {}", gutter, number, self.raw)?;
+        if let Some(span) = &self.span {
+            let start = self.raw.get(..span.start).map_or(0, |s| s.chars().count());
+            let width = self.raw.get(span.clone()).map_or(1, |s| s.chars().count());
+            write!(
+                f,
+                "\n{} | {}{}",
+                gutter,
+                " ".repeat(start),
+                "^".repeat(width.max(1))
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for Malformed {}
+
+impl Malformed {
+    // Returns the malformed line with an error that is not about a single field
+    pub fn new(line: usize, raw: &str, error: String) -> Malformed {
+        Malformed {
+            line,
+            raw: raw.to_string(),
+            field: None,
+            value: None,
+            error,
+            span: None,
+        }
+    }
+
+    // Returns the malformed line of a csv error, the row is the line as read if it was read
+    pub fn csv(
+        line: usize,
+        raw: &str,
+        headers: &StringRecord,
+        row: Option<&StringRecord>,
+        err: &csv::Error,
+    ) -> Malformed {
+        match err.kind() {
+            ErrorKind::Deserialize { err: de, .. } => {
+                // errors of serde such as an unknown variant have no field, the field is found again
+                let culprit = row.and_then(|row| culprit(headers, row));
+                let (index, kind) = match culprit.as_ref().map(|(index, err)| (index, err.kind())) {
+                    Some((index, ErrorKind::Deserialize { err, .. })) => (Some(*index), err.kind()),
+                    _ => (de.field().map(|index| index as usize), de.kind()),
+                };
+                let field = index.and_then(|index| headers.get(index));
+                let value = index.and_then(|index| row.and_then(|row| row.get(index)));
+
+                match (field, value) {
+                    (Some(field), Some(value)) => Malformed {
+                        field: Some(field.to_string()),
+                        value: Some(value.to_string()),
+                        error: explain(field, value, kind),
+                        span: index.and_then(|index| span(raw, index)),
+                        ..Malformed::new(line, raw, String::new())
+                    },
+                    // a field the header does not have
+                    _ => Malformed::new(line, raw, capitalize(&kind.to_string())),
+                }
+            }
+            ErrorKind::UnequalLengths {
+                expected_len, len, ..
+            } => {
+                let mut error = format!(
+                    "The line has {} fields but the header has {}",
+                    len, expected_len
+                );
+                if let Some(amount) = split(raw) {
+                    error.push_str(&format!(
+                        ", the amount `{}` has a thousands separator which splits it in two fields, \
+                         write it as `{}`",
+                        amount,
+                        amount.replace(SEPARATORS, "")
+                    ));
+                }
+
+                Malformed::new(line, raw, error)
+            }
+            _ => Malformed::new(line, raw, err.to_string()),
+        }
+    }
+
+    // Returns the malformed line of a json error, pointing at the column of the error
+    pub fn json(line: usize, raw: &str, err: &serde_json::Error) -> Malformed {
+        // the column is of the last character read, the first is 1
+        let start = raw
+            .char_indices()
+            .nth(err.column().saturating_sub(1))
+            .map_or(raw.len(), |(i, _c)| i);
+        let error = err.to_string();
+        let error = error
+            .rsplit_once(" at line ")
+            .map_or(error.as_str(), |(error, _position)| error);
+
+        Malformed {
+            span: Some(start..start + 1),
+            ..Malformed::new(line, raw, capitalize(error))
+        }
+    }
+}
+
+// Returns the index of the first field that can not be read as its column and the error of it
+fn culprit(headers: &StringRecord, row: &StringRecord) -> Option<(usize, csv::Error)> {
+    headers
+        .iter()
+        .zip(row.iter())
+        .enumerate()
+        .find_map(|(index, (field, value))| {
+            let value = StringRecord::from(vec![value]);
+            let err = match field {
+                "type" => value.deserialize::<Type>(None).err(),
+                "client" => value.deserialize::<u16>(None).err(),
+                "tx" => value.deserialize::<u32>(None).err(),
+                "amount" => value.deserialize::<Option<f64>>(None).err(),
+                "status" => value.deserialize::<Option<Status>>(None).err(),
+                "timestamp" => value.deserialize::<Option<DateTime<Utc>>>(None).err(),
+                "currency" | "to" => value.deserialize::<Option<Currency>>(None).err(),
+                "recipient" => value.deserialize::<Option<u16>>(None).err(),
+                _ => None,
+            };
+
+            err.map(|err| (index, err))
+        })
+}
+
+// Returns the text with a capital first letter, the errors of the crates start in lowercase
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// Returns why the value of the field could not be read
+fn explain(field: &str, value: &str, kind: &DeserializeErrorKind) -> String {
+    if value.is_empty() {
+        return format!("Missing {}", field);
+    }
+
+    match (field, kind) {
+        ("type", _) => format!(
+            "Unknown type `{}`, expected deposit, withdrawal, dispute, resolve, chargeback, \
+             convert, transfer, release or reject",
+            value
+        ),
+        ("client" | "recipient", DeserializeErrorKind::ParseInt(err)) => {
+            whole(field, value, err.kind(), u16::MAX.into())
+        }
+        ("tx", DeserializeErrorKind::ParseInt(err)) => {
+            whole(field, value, err.kind(), u32::MAX.into())
+        }
+        (_, DeserializeErrorKind::ParseFloat(_)) if thousands(value) => format!(
+            "The {} `{}` has a thousands separator, write it as `{}`",
+            field,
+            value,
+            value.replace(SEPARATORS, "")
+        ),
+        (_, DeserializeErrorKind::ParseFloat(_)) => {
+            format!("The {} `{}` is not a number", field, value)
+        }
+        ("timestamp", _) => format!(
+            "The timestamp `{}` is not RFC 3339, such as 2021-01-01T00:00:00Z",
+            value
+        ),
+        (_, DeserializeErrorKind::Message(message)) => {
+            // the errors of the repo start with `Error: `
+            let message = message.strip_prefix("Error: ").unwrap_or(message);
+            format!("The {} `{}` is invalid, {}", field, value, message)
+        }
+        (_, kind) => format!("The {} `{}` is invalid, {}", field, value, kind),
+    }
+}
+
+// Returns why the value of a field of whole numbers up to the max could not be read
+fn whole(field: &str, value: &str, kind: &IntErrorKind, max: u64) -> String {
+    match kind {
+        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => format!(
+            "The {} `{}` is out of range, it is from 0 to {}",
+            field, value, max
+        ),
+        _ => format!(
+            "The {} `{}` is not a whole number from 0 to {}",
+            field, value, max
+        ),
+    }
+}
+
+// Returns true if the value is a number with a separator between its thousands, such as 1,000.0
+fn thousands(value: &str) -> bool {
+    let value = value.strip_prefix('-').unwrap_or(value);
+    let (integer, fraction) = value.split_once('.').unwrap_or((value, "0"));
+    let groups: Vec<&str> = integer.split(SEPARATORS).collect();
+
+    let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    groups.len() > 1
+        && (1..=3).contains(&groups[0].len())
+        && groups.iter().all(|group| digits(group))
+        && groups[1..].iter().all(|group| group.len() == 3)
+        && digits(fraction)
+}
+
+// Returns the amount of a line that was split in two fields by a comma between its thousands
+fn split(raw: &str) -> Option<String> {
+    let fields: Vec<&str> = raw.split(',').map(str::trim).collect();
+
+    fields.windows(2).skip(1).find_map(|pair| {
+        let amount = format!("{},{}", pair[0], pair[1]);
+        thousands(&amount).then_some(amount)
+    })
+}
+
+// Returns the bytes of the field at the index in the line, without the surrounding spaces
+fn span(raw: &str, index: usize) -> Option<Range<usize>> {
+    let mut field = 0;
+    let mut start = 0;
+    let mut quoted = false;
+
+    for (i, c) in raw.char_indices().chain(std::iter::once((raw.len(), ','))) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                if field == index {
+                    let text = &raw[start..i];
+                    let from = start + (text.len() - text.trim_start().len());
+                    let to = (start + text.trim_end().len()).max(from);
+                    return Some(from..to);
+                }
+                field += 1;
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_reader::{Format, Mode, CSV};
+
+    fn load(line: &str) -> Malformed {
+        let text = format!("type,client,tx,amount,timestamp\n{}\n", line);
+        let csv = CSV::parse(&text, Format::Csv, Mode::Lenient(None)).expect("Failed to load csv");
+
+        csv.malformed[0].clone()
+    }
+
+    #[test]
+    fn test_explain() {
+        let malformed = load("depositt,1,2,1.0,");
+        assert_eq!(malformed.line, 2);
+        assert_eq!(malformed.field, Some("type".to_string()));
+        assert_eq!(malformed.value, Some("depositt".to_string()));
+        assert!(malformed.error.starts_with("Unknown type `depositt`"));
+
+        let malformed = load("deposit,70000,2,1.0,");
+        assert_eq!(malformed.field, Some("client".to_string()));
+        assert_eq!(
+            malformed.error,
+            "The client `70000` is out of range, it is from 0 to 65535"
+        );
+
+        let malformed = load("deposit,1,2,\"1,000.5\",");
+        assert_eq!(
+            malformed.error,
+            "The amount `1,000.5` has a thousands separator, write it as `1000.5`"
+        );
+
+        let malformed = load("deposit,1,2,1,000.5,");
+        assert!(malformed.error.starts_with("The line has 6 fields"));
+        assert!(malformed.error.ends_with("write it as `1000.5`"));
+
+        let malformed = load("deposit,1,2,1.0,yesterday");
+        assert_eq!(malformed.field, Some("timestamp".to_string()));
+        assert!(malformed.error.contains("RFC 3339"));
+
+        let malformed = load("deposit,1,x,1.0,");
+        assert_eq!(
+            malformed.error,
+            "The tx `x` is not a whole number from 0 to 4294967295"
+        );
+
+        assert_eq!(load("deposit,,2,1.0,").error, "Missing client");
+    }
+
+    #[test]
+    fn test_display() {
+        let malformed = load("deposit, 70000 ,2,1.0,");
+        assert_eq!(malformed.span, Some(9..14));
+
+        assert_eq!(
+            malformed.to_string(),
+            "Error: The client `70000` is out of range, it is from 0 to 65535\n \
+             --> line 2, field `client`\n  |\n\
+             2 | deposit, 70000 ,2,1.0,\n  |          ^^^^^"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let raw = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "x"}"#;
+        let csv = CSV::parse(raw, Format::Json, Mode::Lenient(None)).expect("Failed to load json");
+        let malformed = &csv.malformed[0];
+
+        assert_eq!(malformed.line, 1);
+        assert!(malformed.error.starts_with("Invalid type"));
+        assert!(malformed.span.is_some());
+    }
+
+    #[test]
+    fn test_thousands() {
+        assert!(thousands("1,000"));
+        assert!(thousands("-12'345.67"));
+        assert!(thousands("1 000 000"));
+        assert!(!thousands("1,00"));
+        assert!(!thousands("1000"));
+        assert!(!thousands("a,bcd"));
+    }
+}
@@ -0,0 +1,268 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::client::Client;
+use crate::config::Config;
+use crate::csv_reader::{self, Record, RecordMap};
+use crate::ledger::Ledger;
+use crate::transction::{self, Applied, Resubmission, Submission};
+
+// Why a submitted record did not change the client
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    // the engine ignored the record, with the reason of the limits or the risk rules if it has one
+    Rejected(Option<String>),
+    // the engine refused the record, such as a record earlier than the previous one
+    Invalid(String),
+    // the tx or idempotency key of the record was submitted before with other contents
+    Conflict(String),
+    // the queue of a service is full, only returned when submitting without waiting
+    Full,
+    // the engine of a service stopped
+    Closed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Rejected(Some(reason)) => write!(f, "Error: Record was rejected ({})", reason),
+            Error::Rejected(None) => write!(f, "Error: Record was rejected"),
+            Error::Invalid(err) => write!(f, "{}", err),
+            Error::Conflict(submission) => write!(
+                f,
+                "Error: {} was submitted before with other contents",
+                submission
+            ),
+            Error::Full => write!(f, "Error: Queue is full"),
+            Error::Closed => write!(f, "Error: Engine is closed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Result of a record submitted to the engine
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    // the record with its row
+    pub record: Record,
+    pub applied: Applied,
+    // true if the record was submitted before, the result is the one of the first time
+    pub replayed: bool,
+}
+
+// State of the engine kept between records that arrive one at a time
+#[derive(Debug, Default)]
+pub struct Engine {
+    pub ledger: Ledger,
+    pub records: RecordMap,
+    pub config: Config,
+    // number of records submitted so far, the row of the next one is one more
+    rows: usize,
+    last: Option<DateTime<Utc>>,
+    // outcomes of the records that can be identified
+    submitted: HashMap<Submission, Outcome>,
+}
+
+impl Engine {
+    pub fn new(config: Config) -> Self {
+        Engine {
+            config,
+            ..Default::default()
+        }
+    }
+
+    // Applies a record as the next row, returns every applied record.
+    // Records with a timestamp must be in chronological order.
+    fn submit(&mut self, mut record: Record) -> Result<Vec<(Record, Applied)>, String> {
+        if let Some(timestamp) = record.timestamp {
+            if self.last.is_some_and(|last| timestamp < last) {
+                return Err("Error: Record is earlier than the previous record".to_string());
+            }
+            self.last = Some(timestamp);
+        }
+
+        self.rows += 1;
+        record.row = self.rows;
+        csv_reader::index(&mut self.records, record.clone());
+
+        let applied = transction::step(&record, &mut self.ledger, &self.records, &self.config);
+        self.ledger.check()?;
+
+        Ok(applied)
+    }
+
+    // Applies a record as the next row, returns the record with its row and its own result.
+    // A record submitted again returns the outcome of the first time without being applied again,
+    // the same tx or idempotency key with other contents is a conflict.
+    pub fn apply(&mut self, record: Record) -> Result<Outcome, Error> {
+        match transction::resubmission(&record, &self.ledger) {
+            Some(Resubmission::Conflict(submission)) => {
+                return Err(Error::Conflict(submission.to_string()))
+            }
+            Some(Resubmission::Replay(submission)) => {
+                let outcome = self
+                    .submitted
+                    .get(&submission)
+                    .ok_or_else(|| Error::Invalid("Error: Record was not applied".to_string()))?;
+
+                return Ok(Outcome {
+                    replayed: true,
+                    ..outcome.clone()
+                });
+            }
+            None => {}
+        }
+        let submission = Submission::of(&record);
+
+        let applied = self.submit(record.clone()).map_err(Error::Invalid)?;
+
+        let (record, applied) = transction::own(&record, applied)
+            .ok_or_else(|| Error::Invalid("Error: Record was not applied".to_string()))?;
+
+        let outcome = Outcome {
+            record,
+            applied,
+            replayed: false,
+        };
+        if let Some(submission) = submission {
+            self.submitted.insert(submission, outcome.clone());
+        }
+
+        Ok(outcome)
+    }
+
+    // Applies a record as the next row, returns the client afterwards or why it was not changed
+    pub fn client(&mut self, record: Record) -> Result<Client, Error> {
+        let outcome = self.apply(record)?;
+
+        match outcome.applied.amount {
+            Some(_) => Ok(outcome.applied.after),
+            None => Err(Error::Rejected(self.reason(outcome.record.row))),
+        }
+    }
+
+    // Returns why the record of a row was rejected by the limits or the risk rules, if it was
+    pub fn reason(&self, row: usize) -> Option<String> {
+        self.ledger.reason(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_reader::Type;
+
+    fn deposit(tx: u32, amount: f64, timestamp: &str) -> Record {
+        Record {
+            timestamp: Some(timestamp.parse().expect("Failed to parse timestamp")),
+            ..Record::new(Type::DEPOSIT, 1, tx, Some(amount))
+        }
+    }
+
+    #[test]
+    fn test_submit_out_of_order() {
+        let mut engine = Engine::default();
+
+        assert!(engine
+            .submit(deposit(1, 1.0, "2021-01-02T00:00:00Z"))
+            .is_ok());
+        assert!(engine
+            .submit(deposit(2, 1.0, "2021-01-01T00:00:00Z"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut engine = Engine::default();
+        engine.config.limits.default.max_amount = Some(5.0);
+
+        let outcome = engine
+            .apply(deposit(1, 2.0, "2021-01-01T00:00:00Z"))
+            .expect("Failed to apply record");
+        assert_eq!(outcome.record.row, 1);
+        assert_eq!(outcome.applied.after.total, 2.0);
+        assert!(!outcome.replayed);
+        assert_eq!(engine.reason(1), None);
+
+        let outcome = engine
+            .apply(deposit(2, 10.0, "2021-01-01T00:00:00Z"))
+            .expect("Failed to apply record");
+        assert_eq!(outcome.applied.amount, None);
+        assert_eq!(
+            engine.reason(outcome.record.row),
+            Some("max-amount".to_string())
+        );
+    }
+
+    #[test]
+    fn test_apply_again() {
+        let mut engine = Engine::default();
+        engine.config.limits.default.max_amount = Some(5.0);
+
+        let first = engine
+            .apply(deposit(1, 2.0, "2021-01-01T00:00:00Z"))
+            .expect("Failed to apply record");
+        let again = engine
+            .apply(deposit(1, 2.0, "2021-01-01T00:00:00Z"))
+            .expect("Failed to apply record");
+        assert!(again.replayed);
+        assert_eq!(again.record.row, first.record.row);
+        assert_eq!(engine.ledger.client(1, None).total, 2.0);
+
+        // a rejected record is rejected again without using a row
+        engine
+            .apply(deposit(2, 10.0, "2021-01-02T00:00:00Z"))
+            .expect("Failed to apply record");
+        let again = engine
+            .apply(deposit(2, 10.0, "2021-01-02T00:00:00Z"))
+            .expect("Failed to apply record");
+        assert!(again.replayed);
+        assert_eq!(again.applied.amount, None);
+        assert_eq!(engine.rows, 2);
+
+        // the same tx with another amount
+        assert_eq!(
+            engine.apply(deposit(1, 3.0, "2021-01-01T00:00:00Z")).err(),
+            Some(Error::Conflict("tx 1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_apply_idempotency_key() {
+        let mut engine = Engine::default();
+        engine
+            .apply(deposit(1, 10.0, "2021-01-01T00:00:00Z"))
+            .expect("Failed to apply record");
+
+        let dispute = |key: &str| Record {
+            r#type: Type::DISPUTE,
+            amount: None,
+            idempotency_key: Some(key.to_string()),
+            ..deposit(1, 0.0, "2021-01-02T00:00:00Z")
+        };
+        assert!(
+            !engine
+                .apply(dispute("a"))
+                .expect("Failed to apply")
+                .replayed
+        );
+        assert!(
+            engine
+                .apply(dispute("a"))
+                .expect("Failed to apply")
+                .replayed
+        );
+        assert_eq!(engine.ledger.client(1, None).held, 10.0);
+
+        let resolve = Record {
+            r#type: Type::RESOLVE,
+            ..dispute("a")
+        };
+        assert_eq!(
+            engine.apply(resolve).err(),
+            Some(Error::Conflict("idempotency key a".to_string()))
+        );
+    }
+}
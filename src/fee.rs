@@ -0,0 +1,161 @@
+use csv::{ReaderBuilder, Trim};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::csv_reader::Type;
+
+// Owner of the fees and exchange accounts. They are never the accounts of a client,
+// so a client with the same id has its own balances and no access to the house.
+pub const HOUSE: u16 = u16::MAX;
+
+// Spec of the fee schedule csv file.
+// A transaction type may have several tiers, the tier with the highest min
+// that is not above the amount is charged. A missing min is a tier starting at 0.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct Fee {
+    pub r#type: Type,
+    pub min: Option<f64>,
+    pub flat: f64,
+    pub percent: f64,
+}
+
+// Contains the fee tiers of every transaction type
+#[derive(Clone, Debug, Default)]
+pub struct FeeSchedule {
+    pub fees: HashMap<Type, Vec<Fee>>,
+}
+
+impl Fee {
+    // Returns the fee charged for the given amount
+    pub fn charge(&self, amount: f64) -> f64 {
+        self.flat + amount * self.percent / 100.0
+    }
+}
+
+impl FeeSchedule {
+    // Loads the provided fee schedule csv file
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut schedule = FeeSchedule::default();
+
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .from_path(path)?;
+
+        for result in rdr.deserialize() {
+            let fee: Fee = result?;
+            // a negative or nan fee would pay the client or could not be ordered
+            let values = [fee.min.unwrap_or(0.0), fee.flat, fee.percent];
+            if values
+                .iter()
+                .any(|value| !value.is_finite() || *value < 0.0)
+            {
+                return Err(format!("Error: Invalid fee of {}", fee.r#type))?;
+            }
+            schedule.insert(fee);
+        }
+
+        Ok(schedule)
+    }
+
+    // Adds a fee tier and keeps the tiers ordered by min
+    pub fn insert(&mut self, fee: Fee) {
+        let tiers = self.fees.entry(fee.r#type).or_default();
+        tiers.push(fee);
+        tiers.sort_by(|a, b| {
+            let a = a.min.unwrap_or(0.0);
+            let b = b.min.unwrap_or(0.0);
+            a.total_cmp(&b)
+        });
+    }
+
+    // Returns the fee charged for a transaction, transaction types without a fee are free
+    pub fn charge(&self, r#type: Type, amount: f64) -> f64 {
+        let tiers = match self.fees.get(&r#type) {
+            Some(tiers) => tiers,
+            None => return 0.0,
+        };
+
+        match tiers
+            .iter()
+            .rev()
+            .find(|fee| fee.min.unwrap_or(0.0) <= amount)
+        {
+            Some(fee) => fee.charge(amount),
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let schedule = FeeSchedule::load("fees.csv").expect("Failed to load fee schedule");
+
+        assert_eq!(schedule.fees.len(), 3);
+        assert_eq!(schedule.fees[&Type::DEPOSIT].len(), 2);
+        assert_eq!(schedule.charge(Type::WITHDRAWAL, 100.0), 0.25);
+        assert_eq!(schedule.charge(Type::DEPOSIT, 2000.0), 5.0);
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        for (i, row) in [
+            "deposit,NaN,0.0,0.5",
+            "deposit,,-1.0,0.0",
+            "withdrawal,,0.0,inf",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let path = std::env::temp_dir().join(format!("invoice_test_fees_{}.csv", i));
+            std::fs::write(&path, format!("type,min,flat,percent\n{}\n", row))
+                .expect("Failed to write csv file");
+
+            let schedule = FeeSchedule::load(&path.display().to_string());
+
+            assert!(schedule.is_err(), "{}", row);
+
+            std::fs::remove_file(&path).expect("Failed to remove csv file");
+        }
+    }
+
+    #[test]
+    fn test_charge() {
+        let mut schedule = FeeSchedule::default();
+        schedule.insert(Fee {
+            r#type: Type::DEPOSIT,
+            min: None,
+            flat: 1.0,
+            percent: 2.0,
+        });
+
+        assert_eq!(schedule.charge(Type::DEPOSIT, 50.0), 2.0);
+        assert_eq!(schedule.charge(Type::WITHDRAWAL, 50.0), 0.0);
+    }
+
+    #[test]
+    fn test_charge_tiers() {
+        let mut schedule = FeeSchedule::default();
+        schedule.insert(Fee {
+            r#type: Type::WITHDRAWAL,
+            min: Some(1000.0),
+            flat: 0.0,
+            percent: 0.5,
+        });
+        schedule.insert(Fee {
+            r#type: Type::WITHDRAWAL,
+            min: Some(100.0),
+            flat: 0.0,
+            percent: 1.0,
+        });
+
+        assert_eq!(schedule.charge(Type::WITHDRAWAL, 50.0), 0.0);
+        assert_eq!(schedule.charge(Type::WITHDRAWAL, 200.0), 2.0);
+        assert_eq!(schedule.charge(Type::WITHDRAWAL, 2000.0), 10.0);
+    }
+}
@@ -0,0 +1,227 @@
+use chrono::NaiveDate;
+use csv::{ReaderBuilder, Trim};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+
+use crate::client::{Client, ClientMap};
+use crate::currency::Currency;
+
+// Two currencies separated by a slash (EUR/USD), a rate is the amount of
+// the second currency paid for one of the first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String")]
+pub struct Pair {
+    pub from: Currency,
+    pub to: Currency,
+}
+
+impl TryFrom<String> for Pair {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.split_once('/') {
+            Some((from, to)) => Ok(Pair {
+                from: from.parse()?,
+                to: to.parse()?,
+            }),
+            None => Err(format!("Error: Invalid currency pair {}", s)),
+        }
+    }
+}
+
+// Spec of the rates csv file
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub date: NaiveDate,
+    pub pair: Pair,
+    pub rate: f64,
+}
+
+// Contains the rates of every pair ordered by date
+#[derive(Clone, Debug, Default)]
+pub struct Rates {
+    pub rates: HashMap<Pair, Vec<Rate>>,
+}
+
+impl Rates {
+    // Loads the provided rates csv file
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut rates = Rates::default();
+
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .from_path(path)?;
+
+        for result in rdr.deserialize() {
+            let rate: Rate = result?;
+            if rate.rate <= 0.0 {
+                return Err(format!("Error: Rate of {} is not positive", rate.date))?;
+            }
+            rates.insert(rate);
+        }
+
+        Ok(rates)
+    }
+
+    // Adds a rate and keeps the rates of its pair ordered by date
+    pub fn insert(&mut self, rate: Rate) {
+        let rates = self.rates.entry(rate.pair).or_default();
+        rates.push(rate);
+        rates.sort_by_key(|rate| rate.date);
+    }
+
+    // Returns the latest rate on or before the date, or the latest rate without a date.
+    // A pair that is only known the other way around uses the inverse rate.
+    pub fn rate(&self, from: Currency, to: Currency, date: Option<NaiveDate>) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+
+        let find = |pair: Pair| {
+            self.rates.get(&pair).and_then(|rates| {
+                rates
+                    .iter()
+                    .rev()
+                    .find(|rate| date.is_none_or(|date| rate.date <= date))
+                    .map(|rate| rate.rate)
+            })
+        };
+
+        match find(Pair { from, to }) {
+            Some(rate) => Some(rate),
+            None => find(Pair { from: to, to: from }).map(|rate| 1.0 / rate),
+        }
+    }
+
+    // Returns the amount converted at the rate of the date
+    pub fn convert(
+        &self,
+        amount: f64,
+        from: Currency,
+        to: Currency,
+        date: Option<NaiveDate>,
+    ) -> Option<f64> {
+        self.rate(from, to, date).map(|rate| amount * rate)
+    }
+}
+
+// Returns the balances of every client in the base currency at the latest rates,
+// balances without a currency are taken to be in the base currency
+pub fn report(clients: &ClientMap, base: Currency, rates: &Rates) -> Result<ClientMap, String> {
+    let mut report = ClientMap::new();
+
+    for client in clients.values() {
+        let rate = match client.currency {
+            Some(currency) => match rates.rate(currency, base, None) {
+                Some(rate) => rate,
+                None => return Err(format!("Error: No rate for {}/{}", currency, base)),
+            },
+            None => 1.0,
+        };
+
+        let converted = report.entry((client.client, Some(base))).or_insert(Client {
+            client: client.client,
+            currency: Some(base),
+            ..Default::default()
+        });
+        converted.available += client.available * rate;
+        converted.held += client.held * rate;
+        converted.total += client.total * rate;
+        converted.locked |= client.locked;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency(code: &str) -> Currency {
+        code.parse().expect("Failed to parse currency")
+    }
+
+    fn rates() -> Rates {
+        let mut rates = Rates::default();
+        for (date, rate) in [((2021, 1, 1), 1.25), ((2021, 2, 1), 1.5)].iter() {
+            rates.insert(Rate {
+                date: NaiveDate::from_ymd_opt(date.0, date.1, date.2)
+                    .expect("Failed to create date"),
+                pair: Pair {
+                    from: currency("EUR"),
+                    to: currency("USD"),
+                },
+                rate: *rate,
+            });
+        }
+
+        rates
+    }
+
+    #[test]
+    fn test_load() {
+        let rates = Rates::load("rates.csv").expect("Failed to load rates");
+
+        assert_eq!(
+            rates.rate(currency("EUR"), currency("USD"), None),
+            Some(1.2)
+        );
+    }
+
+    #[test]
+    fn test_rate() {
+        let rates = rates();
+        let (eur, usd) = (currency("EUR"), currency("USD"));
+
+        assert_eq!(rates.rate(eur, usd, None), Some(1.5));
+        assert_eq!(
+            rates.rate(eur, usd, NaiveDate::from_ymd_opt(2021, 1, 15)),
+            Some(1.25)
+        );
+        assert_eq!(
+            rates.rate(eur, usd, NaiveDate::from_ymd_opt(2020, 12, 31)),
+            None
+        );
+        assert_eq!(rates.rate(usd, eur, None), Some(1.0 / 1.5));
+        assert_eq!(rates.rate(eur, currency("GBP"), None), None);
+        assert_eq!(rates.convert(10.0, eur, usd, None), Some(15.0));
+    }
+
+    #[test]
+    fn test_report() {
+        let rates = rates();
+        let mut clients = ClientMap::new();
+        clients.insert(
+            (1, Some(currency("EUR"))),
+            Client {
+                client: 1,
+                currency: Some(currency("EUR")),
+                available: 10.0,
+                total: 10.0,
+                ..Default::default()
+            },
+        );
+        clients.insert(
+            (1, None),
+            Client {
+                client: 1,
+                available: 5.0,
+                total: 5.0,
+                locked: true,
+                ..Default::default()
+            },
+        );
+
+        let report = report(&clients, currency("USD"), &rates).expect("Failed to report");
+        let client = report[&(1, Some(currency("USD")))];
+
+        assert_eq!(report.len(), 1);
+        assert_eq!(client.available, 20.0);
+        assert_eq!(client.total, 20.0);
+        assert!(client.locked);
+        assert!(super::report(&clients, currency("GBP"), &rates).is_err());
+    }
+}
@@ -0,0 +1,397 @@
+use chrono::{Duration, NaiveDate};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::client::ClientKey;
+use crate::csv_reader::Type;
+use crate::currency::Currency;
+use crate::statement::{self, History, HistoryMap};
+
+// Default templates, can be replaced with Options.template
+const HTML_TEMPLATE: &str = include_str!("../templates/invoice.html");
+const TEXT_TEMPLATE: &str = include_str!("../templates/invoice.txt");
+
+// Output formats of an invoice
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Html,
+    Text,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(Format::Html),
+            "text" => Ok(Format::Text),
+            _ => Err(format!("Error: Unknown invoice format {}", s)),
+        }
+    }
+}
+
+impl Format {
+    // Returns the file extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Html => "html",
+            Format::Text => "txt",
+        }
+    }
+}
+
+// Options used to number, date and render invoices
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub prefix: String,
+    pub start: u32,
+    pub date: NaiveDate,
+    pub terms: i64,
+    pub format: Format,
+    pub template: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            prefix: "INV-".to_string(),
+            start: 1,
+            date: chrono::Local::now().date_naive(),
+            terms: 30,
+            format: Format::Html,
+            template: None,
+        }
+    }
+}
+
+// A single transaction on the invoice
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineItem {
+    pub r#type: Type,
+    pub tx: u32,
+    pub amount: f64,
+    pub fee: f64,
+}
+
+// Sum of the line items of a transaction type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Subtotal {
+    pub r#type: Type,
+    pub count: usize,
+    pub amount: f64,
+    pub fee: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Invoice {
+    pub number: String,
+    pub client: u16,
+    pub currency: Option<Currency>,
+    pub date: NaiveDate,
+    pub due: NaiveDate,
+    pub lines: Vec<LineItem>,
+    pub subtotals: Vec<Subtotal>,
+    pub balance: f64,
+    pub total: f64,
+}
+
+impl Invoice {
+    // Creates an invoice from the history of a client and the fees charged on it
+    pub fn new(number: String, history: &History, options: &Options) -> Self {
+        let mut lines = vec![];
+        let mut subtotals: Vec<Subtotal> = vec![];
+
+        for entry in &history.entries {
+            let amount = entry.amount.unwrap_or(0.0);
+            let fee = entry.fee;
+
+            lines.push(LineItem {
+                r#type: entry.r#type,
+                tx: entry.tx,
+                amount,
+                fee,
+            });
+
+            // subtotals are kept in order of first appearance
+            match subtotals.iter_mut().find(|s| s.r#type == entry.r#type) {
+                Some(subtotal) => {
+                    subtotal.count += 1;
+                    subtotal.amount += amount;
+                    subtotal.fee += fee;
+                }
+                None => subtotals.push(Subtotal {
+                    r#type: entry.r#type,
+                    count: 1,
+                    amount,
+                    fee,
+                }),
+            }
+        }
+
+        let total = subtotals.iter().map(|s| s.fee).sum();
+
+        Invoice {
+            number,
+            client: history.opening.client,
+            currency: history.opening.currency,
+            date: options.date,
+            due: options.date + Duration::days(options.terms),
+            lines,
+            subtotals,
+            balance: history.closing().total,
+            total,
+        }
+    }
+
+    // Renders the invoice by replacing the placeholders of the template
+    pub fn render(&self, format: Format, template: &str) -> String {
+        let lines: Vec<String> = self
+            .lines
+            .iter()
+            .map(|line| match format {
+                Format::Html => format!(
+                    "<tr><td>{}</td><td>{}</td><td>{:.4}</td><td>{:.4}</td></tr>",
+                    line.r#type, line.tx, line.amount, line.fee
+                ),
+                Format::Text => format!(
+                    "{:<12} {:>10} {:>14.4} {:>10.4}",
+                    line.r#type, line.tx, line.amount, line.fee
+                ),
+            })
+            .collect();
+
+        let subtotals: Vec<String> = self
+            .subtotals
+            .iter()
+            .map(|subtotal| match format {
+                Format::Html => format!(
+                    "<tr><td>{}</td><td>{}</td><td>{:.4}</td><td>{:.4}</td></tr>",
+                    subtotal.r#type, subtotal.count, subtotal.amount, subtotal.fee
+                ),
+                Format::Text => format!(
+                    "{:<12} {:>10} {:>14.4} {:>10.4}",
+                    subtotal.r#type, subtotal.count, subtotal.amount, subtotal.fee
+                ),
+            })
+            .collect();
+
+        // text invoices get a header for the columns
+        let (lines, subtotals) = match format {
+            Format::Html => (lines.join("\n"), subtotals.join("\n")),
+            Format::Text => (
+                format!(
+                    "{:<12} {:>10} {:>14} {:>10}\n{}",
+                    "type",
+                    "tx",
+                    "amount",
+                    "fee",
+                    lines.join("\n")
+                ),
+                format!(
+                    "{:<12} {:>10} {:>14} {:>10}\n{}",
+                    "type",
+                    "count",
+                    "amount",
+                    "fee",
+                    subtotals.join("\n")
+                ),
+            ),
+        };
+
+        template
+            .replace("{{number}}", &self.number)
+            .replace("{{client}}", &self.client.to_string())
+            .replace(
+                "{{currency}}",
+                &self.currency.map(|c| c.to_string()).unwrap_or_default(),
+            )
+            .replace("{{date}}", &self.date.to_string())
+            .replace("{{due}}", &self.due.to_string())
+            .replace("{{lines}}", &lines)
+            .replace("{{subtotals}}", &subtotals)
+            .replace("{{balance}}", &format!("{:.4}", self.balance))
+            .replace("{{total}}", &format!("{:.4}", self.total))
+    }
+}
+
+// Creates a numbered invoice for every client and currency in order of client id
+pub fn generate(history: &HistoryMap, options: &Options) -> Vec<Invoice> {
+    let ids: Vec<ClientKey> = statement::sorted(history);
+
+    ids.iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let number = format!("{}{:06}", options.prefix, options.start + i as u32);
+            Invoice::new(number, &history[id], options)
+        })
+        .collect()
+}
+
+// Writes one file per invoice to the provided directory and returns the paths
+pub fn output(
+    invoices: &[Invoice],
+    options: &Options,
+    dir: &Path,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let template = match &options.template {
+        Some(path) => fs::read_to_string(path)?,
+        None => match options.format {
+            Format::Html => HTML_TEMPLATE.to_string(),
+            Format::Text => TEXT_TEMPLATE.to_string(),
+        },
+    };
+
+    fs::create_dir_all(dir)?;
+
+    let mut paths = vec![];
+    for invoice in invoices {
+        let path = dir.join(format!("{}.{}", invoice.number, options.format.extension()));
+        fs::write(&path, invoice.render(options.format, &template))?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::csv_reader::Status;
+    use crate::statement::Entry;
+    use std::collections::HashMap;
+
+    fn history() -> HistoryMap {
+        let mut history: HistoryMap = HashMap::new();
+        let entry = Entry {
+            r#type: Type::DEPOSIT,
+            tx: 1,
+            amount: Some(100.0),
+            fee: 0.5,
+            available: 100.0,
+            held: 0.0,
+            total: 100.0,
+            locked: false,
+            status: Status::NONE,
+        };
+        history.insert(
+            (1, None),
+            History {
+                opening: Client {
+                    client: 1,
+                    ..Default::default()
+                },
+                entries: vec![
+                    entry,
+                    Entry {
+                        tx: 2,
+                        amount: Some(50.0),
+                        fee: 0.25,
+                        available: 150.0,
+                        total: 150.0,
+                        ..entry
+                    },
+                    Entry {
+                        r#type: Type::WITHDRAWAL,
+                        tx: 3,
+                        amount: Some(20.0),
+                        fee: 0.25,
+                        available: 130.0,
+                        total: 130.0,
+                        ..entry
+                    },
+                ],
+            },
+        );
+        history.insert(
+            (2, None),
+            History {
+                opening: Client {
+                    client: 2,
+                    ..Default::default()
+                },
+                entries: vec![entry],
+            },
+        );
+        history.insert(
+            (2, "EUR".parse().ok()),
+            History {
+                opening: Client {
+                    client: 2,
+                    currency: "EUR".parse().ok(),
+                    ..Default::default()
+                },
+                entries: vec![entry],
+            },
+        );
+
+        history
+    }
+
+    fn options() -> Options {
+        Options {
+            date: NaiveDate::from_ymd_opt(2021, 1, 15).expect("Failed to create date"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        let invoices = generate(&history(), &options());
+
+        assert_eq!(invoices.len(), 3);
+        assert_eq!(invoices[0].number, "INV-000001");
+        assert_eq!(invoices[1].number, "INV-000002");
+        assert_eq!(invoices[2].client, 2);
+        assert_eq!(invoices[2].currency, "EUR".parse().ok());
+        assert_eq!(invoices[0].client, 1);
+        assert_eq!(invoices[0].lines.len(), 3);
+        assert_eq!(invoices[0].subtotals.len(), 2);
+        assert_eq!(invoices[0].subtotals[0].count, 2);
+        assert_eq!(invoices[0].subtotals[0].fee, 0.75);
+        assert_eq!(invoices[0].total, 1.0);
+        assert_eq!(invoices[0].balance, 130.0);
+        assert_eq!(invoices[0].due.to_string(), "2021-02-14");
+    }
+
+    #[test]
+    fn test_render() {
+        let invoices = generate(&history(), &options());
+
+        let html = invoices[0].render(Format::Html, HTML_TEMPLATE);
+        assert!(html.contains("<title>Invoice INV-000001</title>"));
+        assert!(
+            html.contains("<tr><td>withdrawal</td><td>3</td><td>20.0000</td><td>0.2500</td></tr>")
+        );
+        assert!(html.contains("Total due: 1.0000"));
+        assert!(!html.contains("{{"));
+
+        let text = invoices[0].render(Format::Text, TEXT_TEMPLATE);
+        assert!(text.contains("Due date: 2021-02-14"));
+        assert!(text.contains("Total due:       1.0000"));
+        assert!(!text.contains("{{"));
+
+        let text = invoices[2].render(Format::Text, TEXT_TEMPLATE);
+        assert!(text.contains("Total due:       0.5000 EUR"));
+    }
+
+    #[test]
+    fn test_output() {
+        let dir = std::env::temp_dir().join("invoice_test_output");
+        let options = Options {
+            format: Format::Text,
+            ..options()
+        };
+        let invoices = generate(&history(), &options);
+
+        let paths = output(&invoices, &options, &dir).expect("Failed to output invoices");
+
+        assert_eq!(paths.len(), 3);
+        assert!(paths[0].ends_with("INV-000001.txt"));
+        assert!(paths[1].exists());
+
+        fs::remove_dir_all(&dir).expect("Failed to remove invoices");
+    }
+}
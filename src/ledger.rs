@@ -0,0 +1,567 @@
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+
+use crate::client::{Client, ClientMap};
+use crate::csv_reader::{Record, Type};
+use crate::currency::Currency;
+use crate::fee::HOUSE;
+use crate::lifecycle::LifecycleMap;
+use crate::limits::{ActivityMap, Rejection};
+use crate::risk::{Action, Flag};
+use crate::transction::{Conflict, Submission};
+
+// Largest difference from zero the trial balance may have due to floating point
+const TOLERANCE: f64 = 1e-9;
+
+// Accounts of a client. External is the world outside of the ledger,
+// deposits come from it and withdrawals go to it. Fees and Exchange belong to the house account,
+// conversions pay into the exchange account in one currency and out of it in another.
+// Pending holds the deposits that have not cleared yet, the client sees it as held.
+// OnHold keeps the deposits and withdrawals held by the risk rules until they are reviewed,
+// the client sees it as held as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Account {
+    Available,
+    Held,
+    Pending,
+    OnHold,
+    External,
+    Fees,
+    Exchange,
+}
+
+impl Account {
+    // Returns true for the accounts of the house, which are never the accounts of a client
+    pub fn house(&self) -> bool {
+        matches!(self, Account::Fees | Account::Exchange)
+    }
+}
+
+// Create a type for easier fn definitions
+pub type Key = (u16, Account);
+
+// Moves an amount from one account to another, both accounts are in the same currency
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Posting {
+    // row of the record the posting was made for
+    pub row: usize,
+    pub tx: u32,
+    pub currency: Option<Currency>,
+    pub from: Key,
+    pub to: Key,
+    pub amount: f64,
+}
+
+// A transaction under dispute, amount is the part of the transaction that is disputed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dispute {
+    pub client: u16,
+    pub currency: Option<Currency>,
+    pub opened: Option<DateTime<Utc>>,
+    pub amount: f64,
+    // part of the amount that was held from a deposit that had not cleared
+    pub pending: f64,
+}
+
+// A deposit that has not cleared yet, amount is the part of it that is still pending
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pending {
+    pub client: u16,
+    pub currency: Option<Currency>,
+    pub row: usize,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub amount: f64,
+}
+
+// A deposit or withdrawal held by the risk rules, amount is the part of it in the on hold account.
+// A released record is no longer on hold, a rejected one stays here so it is never applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hold {
+    pub client: u16,
+    pub currency: Option<Currency>,
+    pub r#type: Type,
+    pub amount: f64,
+    pub rejected: bool,
+}
+
+// Journal of postings and the balances of every account in every currency.
+// Client balances are derived from the accounts and are never updated directly.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    pub clients: ClientMap,
+    pub journal: Vec<Posting>,
+    // open disputes by tx
+    pub disputes: HashMap<u32, Dispute>,
+    // amount charged back by tx
+    pub charged_back: HashMap<u32, f64>,
+    // deposits that have not cleared by tx
+    pub pending: HashMap<u32, Pending>,
+    // deposits and withdrawals held by the risk rules by tx
+    pub holds: HashMap<u32, Hold>,
+    // disputes, resolves and chargebacks by tx
+    pub lifecycles: LifecycleMap,
+    // deposits and withdrawals with a timestamp by client, for the limits over time
+    pub activity: ActivityMap,
+    // records rejected by the limits
+    pub rejections: Vec<Rejection>,
+    // records scored as not allowed by the risk rules
+    pub flags: Vec<Flag>,
+    // first record of every tx and idempotency key
+    pub submitted: HashMap<Submission, Record>,
+    // records with the tx or idempotency key of an earlier record with other contents
+    pub conflicts: Vec<Conflict>,
+    // row of the record being applied
+    pub row: usize,
+    // a locked client is locked in every currency
+    locked: HashSet<u16>,
+    balances: HashMap<(Key, Option<Currency>), f64>,
+}
+
+impl Ledger {
+    // Adds the client to the ledger if it does not exist yet
+    pub fn open(&mut self, client: &Client) {
+        let opened = self.client(client.client, client.currency);
+        self.clients
+            .entry((client.client, client.currency))
+            .or_insert(opened);
+    }
+
+    // Moves the amount between the accounts and updates the clients of both
+    pub fn post(&mut self, tx: u32, currency: Option<Currency>, from: Key, to: Key, amount: f64) {
+        self.journal.push(Posting {
+            row: self.row,
+            tx,
+            currency,
+            from,
+            to,
+            amount,
+        });
+
+        *self.balances.entry((from, currency)).or_insert(0.0) -= amount;
+        *self.balances.entry((to, currency)).or_insert(0.0) += amount;
+
+        // the accounts of the house are not a client
+        if !from.1.house() {
+            self.derive(from.0, currency);
+        }
+        if !to.1.house() {
+            self.derive(to.0, currency);
+        }
+    }
+
+    // Returns one ledger of the ledgers of different clients. The postings are replayed in the order
+    // of their rows, so the accounts they share (the house) sum up as if they were made by one ledger.
+    pub fn merge(ledgers: Vec<Ledger>) -> Ledger {
+        let mut merged = Ledger::default();
+
+        for ledger in ledgers {
+            merged.clients.extend(ledger.clients);
+            merged.journal.extend(ledger.journal);
+            merged.disputes.extend(ledger.disputes);
+            merged.charged_back.extend(ledger.charged_back);
+            merged.pending.extend(ledger.pending);
+            merged.holds.extend(ledger.holds);
+            merged.lifecycles.extend(ledger.lifecycles);
+            merged.activity.extend(ledger.activity);
+            merged.rejections.extend(ledger.rejections);
+            merged.flags.extend(ledger.flags);
+            merged.submitted.extend(ledger.submitted);
+            merged.conflicts.extend(ledger.conflicts);
+            merged.locked.extend(ledger.locked);
+        }
+        merged.journal.sort_by_key(|posting| posting.row);
+        merged.rejections.sort_by_key(|rejection| rejection.row);
+        merged.flags.sort_by_key(|flag| flag.row);
+        merged.conflicts.sort_by_key(|conflict| conflict.row);
+
+        for posting in &merged.journal {
+            *merged
+                .balances
+                .entry((posting.from, posting.currency))
+                .or_insert(0.0) -= posting.amount;
+            *merged
+                .balances
+                .entry((posting.to, posting.currency))
+                .or_insert(0.0) += posting.amount;
+        }
+
+        let keys: HashSet<(u16, Option<Currency>)> = merged
+            .balances
+            .keys()
+            .filter(|((_id, account), _currency)| !account.house())
+            .map(|((id, _account), currency)| (*id, *currency))
+            .collect();
+        for (id, currency) in keys {
+            merged.derive(id, currency);
+        }
+
+        merged
+    }
+
+    // Moves the funds of a client in every currency to another ledger, so a record the other ledger
+    // applies can change the client. The funds leave through the external account of the client and
+    // come in through the one of the other ledger, both ledgers keep a trial balance of zero.
+    // A locked client is locked in the other ledger as well.
+    pub fn hand_over(&mut self, id: u16, tx: u32, to: &mut Ledger) {
+        let mut keys: Vec<(Account, Option<Currency>)> = self
+            .balances
+            .iter()
+            .filter(|(((client, account), _currency), balance)| {
+                *client == id && *account != Account::External && **balance != 0.0
+            })
+            .map(|(((_client, account), currency), _balance)| (*account, *currency))
+            .collect();
+        keys.sort_by_key(|(account, currency)| (*currency, *account as u8));
+
+        for (account, currency) in keys {
+            let amount = self.balance((id, account), currency);
+            self.post(tx, currency, (id, account), (id, Account::External), amount);
+            to.post(tx, currency, (id, Account::External), (id, account), amount);
+        }
+
+        if self.locked.contains(&id) {
+            to.lock(id);
+        }
+    }
+
+    // Returns the balance of an account in the given currency
+    pub fn balance(&self, key: Key, currency: Option<Currency>) -> f64 {
+        *self.balances.get(&(key, currency)).unwrap_or(&0.0)
+    }
+
+    // Returns the client with the given id and currency, clients without postings have no funds
+    pub fn client(&self, id: u16, currency: Option<Currency>) -> Client {
+        match self.clients.get(&(id, currency)) {
+            Some(client) => *client,
+            None => Client {
+                client: id,
+                currency,
+                locked: self.locked.contains(&id),
+                ..Default::default()
+            },
+        }
+    }
+
+    // Returns the part of a transaction of the given amount that is not disputed or charged back
+    pub fn disputable(&self, tx: u32, amount: f64) -> f64 {
+        let disputed = self.disputes.get(&tx).map_or(0.0, |dispute| dispute.amount);
+        let charged_back = *self.charged_back.get(&tx).unwrap_or(&0.0);
+
+        amount - disputed - charged_back
+    }
+
+    // Takes up to the amount from the pending part of a deposit, returns the amount taken
+    pub fn take_pending(&mut self, tx: u32, amount: f64) -> f64 {
+        match self.pending.get_mut(&tx) {
+            Some(pending) => {
+                let taken = pending.amount.min(amount);
+                pending.amount -= taken;
+                taken
+            }
+            None => 0.0,
+        }
+    }
+
+    // Freezes the client in every currency
+    pub fn lock(&mut self, id: u16) {
+        self.locked.insert(id);
+        for ((client, _currency), balances) in self.clients.iter_mut() {
+            if *client == id {
+                balances.locked = true;
+            }
+        }
+    }
+
+    // Returns the fees the house collected in a currency
+    pub fn fees(&self, currency: Option<Currency>) -> f64 {
+        self.balance((HOUSE, Account::Fees), currency)
+    }
+
+    // Returns the sum of every account in a currency, which is zero for a consistent ledger
+    pub fn trial_balance(&self, currency: Option<Currency>) -> f64 {
+        self.balances
+            .iter()
+            .filter(|((_key, c), _balance)| *c == currency)
+            .map(|(_key, balance)| balance)
+            .sum()
+    }
+
+    // Returns why the record of a row was rejected by the limits or the risk rules, if it was
+    pub fn reason(&self, row: usize) -> Option<String> {
+        let rejection = self
+            .rejections
+            .iter()
+            .rev()
+            .find(|rejection| rejection.row == row);
+        if let Some(rejection) = rejection {
+            return Some(rejection.reason.to_string());
+        }
+
+        self.flags
+            .iter()
+            .rev()
+            .find(|flag| flag.row == row && flag.action == Action::Reject)
+            .map(|_flag| "risk".to_string())
+    }
+
+    // Returns an error if the trial balance of a currency does not sum to zero
+    pub fn check(&self) -> Result<(), String> {
+        let currencies: HashSet<Option<Currency>> = self
+            .balances
+            .keys()
+            .map(|(_key, currency)| *currency)
+            .collect();
+
+        for currency in currencies {
+            let sum = self.trial_balance(currency);
+            if sum.abs() > TOLERANCE {
+                return Err(match currency {
+                    Some(currency) => format!(
+                        "Error: Trial balance of {} sums to {} instead of 0",
+                        currency, sum
+                    ),
+                    None => format!("Error: Trial balance sums to {} instead of 0", sum),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Updates the balances of a client in a currency from its accounts
+    fn derive(&mut self, id: u16, currency: Option<Currency>) {
+        let mut client = self.client(id, currency);
+
+        client.available = self.balance((id, Account::Available), currency);
+        client.held = self.balance((id, Account::Held), currency)
+            + self.balance((id, Account::Pending), currency)
+            + self.balance((id, Account::OnHold), currency);
+
+        client.total = client.available + client.held;
+
+        self.clients.insert((id, currency), client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_post() {
+        let mut ledger = Ledger::default();
+
+        ledger.post(
+            1,
+            None,
+            (1, Account::External),
+            (1, Account::Available),
+            10.0,
+        );
+        ledger.post(2, None, (1, Account::Available), (1, Account::Held), 4.0);
+
+        let client = ledger.client(1, None);
+        assert_eq!(client.available, 6.0);
+        assert_eq!(client.held, 4.0);
+        assert_eq!(client.total, 10.0);
+        assert_eq!(ledger.balance((1, Account::External), None), -10.0);
+        assert_eq!(ledger.journal.len(), 2);
+        assert_eq!(ledger.trial_balance(None), 0.0);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut ledger = Ledger::default();
+
+        ledger.post(
+            1,
+            None,
+            (1, Account::External),
+            (1, Account::Available),
+            10.0,
+        );
+        ledger.post(
+            1,
+            None,
+            (1, Account::Available),
+            (HOUSE, Account::Fees),
+            0.5,
+        );
+
+        assert_eq!(ledger.client(1, None).total, 9.5);
+        assert_eq!(ledger.fees(None), 0.5);
+        assert_eq!(ledger.client(HOUSE, None).total, 0.0);
+        assert!(!ledger.clients.contains_key(&(HOUSE, None)));
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn test_pending() {
+        let mut ledger = Ledger::default();
+
+        ledger.post(1, None, (1, Account::External), (1, Account::Pending), 10.0);
+        ledger.pending.insert(
+            1,
+            Pending {
+                client: 1,
+                currency: None,
+                row: 1,
+                timestamp: None,
+                amount: 10.0,
+            },
+        );
+
+        let client = ledger.client(1, None);
+        assert_eq!(client.available, 0.0);
+        assert_eq!(client.held, 10.0);
+        assert_eq!(client.total, 10.0);
+        assert_eq!(ledger.take_pending(1, 4.0), 4.0);
+        assert_eq!(ledger.take_pending(1, 8.0), 6.0);
+        assert_eq!(ledger.take_pending(2, 1.0), 0.0);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut first = Ledger::default();
+        let mut second = Ledger::default();
+
+        first.row = 1;
+        first.post(
+            1,
+            None,
+            (1, Account::External),
+            (1, Account::Available),
+            10.0,
+        );
+        first.post(
+            1,
+            None,
+            (1, Account::Available),
+            (HOUSE, Account::Fees),
+            0.1,
+        );
+        second.row = 2;
+        second.post(
+            2,
+            None,
+            (2, Account::External),
+            (2, Account::Available),
+            5.0,
+        );
+        second.post(
+            2,
+            None,
+            (2, Account::Available),
+            (HOUSE, Account::Fees),
+            0.2,
+        );
+        second.lock(2);
+
+        let merged = Ledger::merge(vec![second, first]);
+        assert_eq!(merged.journal[0].row, 1);
+        assert_eq!(merged.client(1, None).total, 9.9);
+        assert_eq!(merged.client(2, None).total, 4.8);
+        assert!(merged.client(2, None).locked);
+        assert_eq!(merged.fees(None), 0.1 + 0.2);
+        assert!(merged.check().is_ok());
+    }
+
+    #[test]
+    fn test_hand_over() {
+        let mut ledger = Ledger::default();
+        let mut other = Ledger::default();
+        ledger.post(
+            1,
+            None,
+            (1, Account::External),
+            (1, Account::Available),
+            10.0,
+        );
+        ledger.post(2, None, (1, Account::Available), (1, Account::Held), 4.0);
+        ledger.lock(1);
+
+        ledger.hand_over(1, 3, &mut other);
+
+        assert_eq!(ledger.client(1, None).total, 0.0);
+        assert_eq!(other.client(1, None).available, 6.0);
+        assert_eq!(other.client(1, None).held, 4.0);
+        assert!(other.client(1, None).locked);
+        assert!(ledger.check().is_ok());
+        assert!(other.check().is_ok());
+
+        other.hand_over(1, 3, &mut ledger);
+        assert_eq!(ledger.client(1, None).total, 10.0);
+        assert_eq!(other.client(1, None).total, 0.0);
+    }
+
+    #[test]
+    fn test_check() {
+        let mut ledger = Ledger::default();
+
+        ledger.post(
+            1,
+            None,
+            (1, Account::External),
+            (1, Account::Available),
+            10.0,
+        );
+        ledger.balances.insert(((1, Account::Held), None), 1.0);
+
+        assert!(ledger.check().is_err());
+    }
+
+    #[test]
+    fn test_lock() {
+        let mut ledger = Ledger::default();
+
+        ledger.post(
+            1,
+            None,
+            (1, Account::External),
+            (1, Account::Available),
+            10.0,
+        );
+        ledger.lock(1);
+        ledger.post(
+            2,
+            None,
+            (1, Account::External),
+            (1, Account::Available),
+            1.0,
+        );
+
+        assert!(ledger.client(1, None).locked);
+        assert_eq!(ledger.client(1, None).total, 11.0);
+    }
+
+    #[test]
+    fn test_currencies() {
+        let mut ledger = Ledger::default();
+        let eur = "EUR".parse().ok();
+        let usd = "USD".parse().ok();
+
+        ledger.post(
+            1,
+            eur,
+            (1, Account::External),
+            (1, Account::Available),
+            10.0,
+        );
+        ledger.post(2, usd, (1, Account::External), (1, Account::Available), 4.0);
+        ledger.lock(1);
+
+        assert_eq!(ledger.client(1, eur).total, 10.0);
+        assert_eq!(ledger.client(1, usd).total, 4.0);
+        assert_eq!(ledger.client(1, None).total, 0.0);
+        assert!(ledger.client(1, usd).locked);
+        assert!(ledger.client(1, None).locked);
+        assert_eq!(ledger.trial_balance(eur), 0.0);
+
+        ledger.balances.insert(((1, Account::Held), usd), 1.0);
+        assert_eq!(
+            ledger.check(),
+            Err("Error: Trial balance of USD sums to 1 instead of 0".to_string())
+        );
+    }
+}
@@ -0,0 +1,23 @@
+pub mod audit;
+pub mod cli;
+pub mod client;
+pub mod config;
+pub mod csv_reader;
+pub mod currency;
+pub mod diagnostic;
+pub mod engine;
+pub mod fee;
+pub mod fx;
+pub mod invoice;
+pub mod ledger;
+pub mod lifecycle;
+pub mod limits;
+pub mod parallel;
+pub mod risk;
+pub mod server;
+pub mod service;
+pub mod shared;
+pub mod statement;
+pub mod stream;
+pub mod transction;
+pub mod validate;
@@ -0,0 +1,174 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+
+use crate::csv_reader::{Record, RecordMap, Status, Type};
+
+// Create a type for easier fn definitions
+pub type LifecycleMap = HashMap<u32, Lifecycle>;
+
+// A dispute, resolve or chargeback applied to a transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    pub row: usize,
+    pub r#type: Type,
+    pub amount: f64,
+    pub timestamp: Option<DateTime<Utc>>,
+    // status of the transaction after the event
+    pub status: Status,
+}
+
+// Status and events of a disputed transaction
+#[derive(Debug, Clone)]
+pub struct Lifecycle {
+    pub status: Status,
+    pub events: Vec<Event>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Lifecycle {
+            status: Status::NONE,
+            events: vec![],
+        }
+    }
+}
+
+impl Lifecycle {
+    // Adds an applied record, open is true if part of the tx is still under dispute afterwards
+    pub fn push(&mut self, record: &Record, amount: f64, open: bool) {
+        self.status = match record.r#type {
+            _ if open => Status::DISPUTED,
+            Type::RESOLVE => Status::RESOLVED,
+            Type::CHARGEBACK => Status::CHARGEBACKED,
+            _ => self.status,
+        };
+
+        self.events.push(Event {
+            row: record.row,
+            r#type: record.r#type,
+            amount,
+            timestamp: record.timestamp,
+            status: self.status,
+        });
+    }
+
+    // Returns the number of times the tx was resolved
+    pub fn resolved(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|event| event.r#type == Type::RESOLVE)
+            .count()
+    }
+}
+
+// Writes the lifecycle of a transaction as plain text
+pub fn write_text<W: Write>(
+    tx: u32,
+    lifecycles: &LifecycleMap,
+    records: &RecordMap,
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let record = match records.get(&tx) {
+        Some(record) => record,
+        None => return Err(format!("Error: Unknown tx {}", tx))?,
+    };
+
+    let lifecycle = lifecycles.get(&tx).cloned().unwrap_or_default();
+
+    writeln!(
+        writer,
+        "Lifecycle of tx {} ({} of {:.4} by client {})",
+        tx,
+        record.r#type,
+        record.amount.unwrap_or(0.0),
+        record.client
+    )?;
+    writeln!(writer)?;
+
+    if lifecycle.events.is_empty() {
+        writeln!(writer, "Never disputed")?;
+        return Ok(());
+    }
+
+    writeln!(
+        writer,
+        "{:<6} {:<12} {:>10}  {:<14} timestamp",
+        "row", "type", "amount", "status"
+    )?;
+    for event in &lifecycle.events {
+        let line = format!(
+            "{:<6} {:<12} {:>10.4}  {:<14} {}",
+            event.row,
+            event.r#type,
+            event.amount,
+            event.status,
+            match event.timestamp {
+                Some(timestamp) => timestamp.to_rfc3339(),
+                None => "".to_string(),
+            }
+        );
+        writeln!(writer, "{}", line.trim_end())?;
+    }
+    writeln!(writer)?;
+    writeln!(writer, "Status: {}", lifecycle.status)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(r#type: Type, row: usize) -> Record {
+        Record {
+            row,
+            ..Record::new(r#type, 1, 1, None)
+        }
+    }
+
+    #[test]
+    fn test_push() {
+        let mut lifecycle = Lifecycle::default();
+
+        lifecycle.push(&record(Type::DISPUTE, 2), 10.0, true);
+        lifecycle.push(&record(Type::RESOLVE, 3), 10.0, false);
+        lifecycle.push(&record(Type::DISPUTE, 4), 10.0, true);
+        lifecycle.push(&record(Type::CHARGEBACK, 5), 4.0, true);
+
+        assert_eq!(lifecycle.status, Status::DISPUTED);
+        assert_eq!(lifecycle.events[1].status, Status::RESOLVED);
+        assert_eq!(lifecycle.events[3].row, 5);
+        assert_eq!(lifecycle.resolved(), 1);
+
+        lifecycle.push(&record(Type::CHARGEBACK, 6), 6.0, false);
+        assert_eq!(lifecycle.status, Status::CHARGEBACKED);
+    }
+
+    #[test]
+    fn test_write_text() {
+        let mut records = HashMap::new();
+        records.insert(
+            1,
+            Record {
+                r#type: Type::DEPOSIT,
+                amount: Some(10.0),
+                ..record(Type::DEPOSIT, 1)
+            },
+        );
+        let mut lifecycles = HashMap::new();
+        let lifecycle: &mut Lifecycle = lifecycles.entry(1).or_default();
+        lifecycle.push(&record(Type::DISPUTE, 2), 10.0, true);
+        lifecycle.push(&record(Type::RESOLVE, 3), 10.0, false);
+
+        let mut out = vec![];
+        write_text(1, &lifecycles, &records, &mut out).expect("Failed to write lifecycle");
+        let text = String::from_utf8(out).expect("Failed to read lifecycle");
+
+        assert!(text.contains("Lifecycle of tx 1 (deposit of 10.0000 by client 1)"));
+        assert!(text.contains("2      dispute         10.0000  disputed"));
+        assert!(text.contains("Status: resolved"));
+        assert!(write_text(2, &lifecycles, &records, &mut vec![]).is_err());
+    }
+}
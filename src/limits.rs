@@ -0,0 +1,330 @@
+use chrono::{DateTime, Duration, Utc};
+use csv::{ReaderBuilder, Trim};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+
+use crate::client::Client;
+use crate::csv_reader::{Record, Type};
+use crate::currency::Currency;
+
+// Create a type for easier fn definitions, the movements are kept per client and currency
+pub type ActivityMap = HashMap<(u16, Option<Currency>), Vec<Movement>>;
+
+// Spec of the limits csv file. A row without a client is the default of every client,
+// the empty fields of a client row fall back to the default.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+pub struct Limit {
+    pub client: Option<u16>,
+    // largest amount of a single deposit or withdrawal
+    pub max_amount: Option<f64>,
+    // largest sum of withdrawals on a single day
+    pub daily_withdrawal: Option<f64>,
+    // largest number of deposits and withdrawals within the window
+    pub max_transactions: Option<usize>,
+    // window of max_transactions in hours, a day if empty
+    pub window: Option<i64>,
+    // smallest available balance a withdrawal may leave
+    pub min_balance: Option<f64>,
+}
+
+// Contains the default limit and the limits of single clients
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub default: Limit,
+    pub clients: HashMap<u16, Limit>,
+}
+
+// A deposit or withdrawal with a timestamp, used by the rules over time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Movement {
+    pub timestamp: DateTime<Utc>,
+    pub r#type: Type,
+    pub amount: f64,
+}
+
+// Why a record was rejected by the limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Reason {
+    #[serde(rename = "max-amount")]
+    MaxAmount,
+    #[serde(rename = "daily-withdrawal")]
+    DailyWithdrawal,
+    #[serde(rename = "velocity")]
+    Velocity,
+    #[serde(rename = "min-balance")]
+    MinBalance,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = match self {
+            Reason::MaxAmount => "max-amount",
+            Reason::DailyWithdrawal => "daily-withdrawal",
+            Reason::Velocity => "velocity",
+            Reason::MinBalance => "min-balance",
+        };
+
+        f.pad(code)
+    }
+}
+
+// A record rejected by the limits
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Rejection {
+    pub row: usize,
+    pub client: u16,
+    pub tx: u32,
+    pub r#type: Type,
+    pub amount: f64,
+    pub reason: Reason,
+}
+
+impl Limit {
+    // Returns the limit with its empty fields taken from the default
+    fn or(self, default: Limit) -> Limit {
+        Limit {
+            client: self.client,
+            max_amount: self.max_amount.or(default.max_amount),
+            daily_withdrawal: self.daily_withdrawal.or(default.daily_withdrawal),
+            max_transactions: self.max_transactions.or(default.max_transactions),
+            window: self.window.or(default.window),
+            min_balance: self.min_balance.or(default.min_balance),
+        }
+    }
+}
+
+impl Limits {
+    // Loads the provided limits csv file
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut limits = Limits::default();
+
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .from_path(path)?;
+
+        for result in rdr.deserialize() {
+            let limit: Limit = result?;
+            match limit.client {
+                Some(client) => {
+                    limits.clients.insert(client, limit);
+                }
+                None => limits.default = limit,
+            }
+        }
+
+        Ok(limits)
+    }
+
+    // Returns the limit of a client
+    pub fn get(&self, client: u16) -> Limit {
+        match self.clients.get(&client) {
+            Some(limit) => limit.or(self.default),
+            None => self.default,
+        }
+    }
+
+    // Returns the reason a deposit or withdrawal breaks the limits of the client, if any.
+    // The fee is charged on top of a withdrawal, rules over time only apply to records with a timestamp.
+    pub fn check(
+        &self,
+        record: &Record,
+        client: &Client,
+        fee: f64,
+        activity: Option<&Vec<Movement>>,
+    ) -> Result<(), Reason> {
+        let limit = self.get(client.client);
+        let amount = record.amount.unwrap_or(0.0);
+        let activity: &[Movement] = activity.map_or(&[], |activity| activity);
+
+        if limit.max_amount.is_some_and(|max| amount > max) {
+            return Err(Reason::MaxAmount);
+        }
+
+        if record.r#type == Type::WITHDRAWAL
+            && limit
+                .min_balance
+                .is_some_and(|min| client.available - amount - fee < min)
+        {
+            return Err(Reason::MinBalance);
+        }
+
+        let now = match record.timestamp {
+            Some(now) => now,
+            None => return Ok(()),
+        };
+
+        if let (Type::WITHDRAWAL, Some(cap)) = (record.r#type, limit.daily_withdrawal) {
+            let today: f64 = activity
+                .iter()
+                .filter(|movement| movement.r#type == Type::WITHDRAWAL)
+                .filter(|movement| movement.timestamp.date_naive() == now.date_naive())
+                .map(|movement| movement.amount)
+                .sum();
+            if today + amount > cap {
+                return Err(Reason::DailyWithdrawal);
+            }
+        }
+
+        if let Some(max) = limit.max_transactions {
+            let window = Duration::hours(limit.window.unwrap_or(24));
+            let count = activity
+                .iter()
+                .filter(|movement| now - movement.timestamp < window)
+                .count();
+            if count >= max {
+                return Err(Reason::Velocity);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Writes the rejected records as csv
+pub fn write_csv<W: Write>(rejections: &[Rejection], writer: W) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+
+    for rejection in rejections {
+        wtr.serialize(rejection)?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(r#type: Type, amount: f64, timestamp: Option<&str>) -> Record {
+        Record {
+            timestamp: timestamp.map(|t| t.parse().expect("Failed to parse timestamp")),
+            ..Record::new(r#type, 1, 1, Some(amount))
+        }
+    }
+
+    fn client(available: f64) -> Client {
+        Client {
+            client: 1,
+            available,
+            total: available,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        let limits = Limits::load("limits.csv").expect("Failed to load limits");
+
+        assert_eq!(limits.default.max_amount, Some(10000.0));
+        assert_eq!(limits.get(4).max_amount, Some(50000.0));
+        assert_eq!(limits.get(4).min_balance, limits.default.min_balance);
+    }
+
+    #[test]
+    fn test_check() {
+        let limits = Limits {
+            default: Limit {
+                max_amount: Some(100.0),
+                min_balance: Some(10.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            limits.check(&record(Type::DEPOSIT, 200.0, None), &client(0.0), 0.0, None),
+            Err(Reason::MaxAmount)
+        );
+        assert_eq!(
+            limits.check(
+                &record(Type::WITHDRAWAL, 50.0, None),
+                &client(55.0),
+                0.0,
+                None
+            ),
+            Err(Reason::MinBalance)
+        );
+        assert_eq!(
+            limits.check(
+                &record(Type::WITHDRAWAL, 40.0, None),
+                &client(55.0),
+                0.0,
+                None
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            limits.check(
+                &record(Type::WITHDRAWAL, 40.0, None),
+                &client(55.0),
+                6.0,
+                None
+            ),
+            Err(Reason::MinBalance)
+        );
+    }
+
+    #[test]
+    fn test_check_over_time() {
+        let limits = Limits {
+            default: Limit {
+                daily_withdrawal: Some(100.0),
+                max_transactions: Some(2),
+                window: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let activity = vec![
+            Movement {
+                timestamp: "2021-01-01T10:00:00Z"
+                    .parse()
+                    .expect("Failed to parse timestamp"),
+                r#type: Type::WITHDRAWAL,
+                amount: 80.0,
+            },
+            Movement {
+                timestamp: "2021-01-01T12:00:00Z"
+                    .parse()
+                    .expect("Failed to parse timestamp"),
+                r#type: Type::DEPOSIT,
+                amount: 10.0,
+            },
+        ];
+
+        let withdrawal = record(Type::WITHDRAWAL, 30.0, Some("2021-01-01T15:00:00Z"));
+        assert_eq!(
+            limits.check(&withdrawal, &client(500.0), 0.0, Some(&activity)),
+            Err(Reason::DailyWithdrawal)
+        );
+
+        let withdrawal = record(Type::WITHDRAWAL, 30.0, Some("2021-01-02T15:00:00Z"));
+        assert_eq!(
+            limits.check(&withdrawal, &client(500.0), 0.0, Some(&activity)),
+            Ok(())
+        );
+
+        let deposit = record(Type::DEPOSIT, 1.0, Some("2021-01-01T12:30:00Z"));
+        let mut activity = activity;
+        activity[0].timestamp = "2021-01-01T12:10:00Z"
+            .parse()
+            .expect("Failed to parse timestamp");
+        assert_eq!(
+            limits.check(&deposit, &client(500.0), 0.0, Some(&activity)),
+            Err(Reason::Velocity)
+        );
+
+        // records without a timestamp are not checked over time
+        let withdrawal = record(Type::WITHDRAWAL, 30.0, None);
+        assert_eq!(
+            limits.check(&withdrawal, &client(500.0), 0.0, Some(&activity)),
+            Ok(())
+        );
+    }
+}
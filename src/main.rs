@@ -1,39 +1,267 @@
-use std::collections::HashMap;
 use std::env;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
 
-mod client;
-mod csv_reader;
-mod transction;
+use ::invoice::cli::{self, Command, Exit};
+use ::invoice::csv_reader::{Mode, CSV};
+use ::invoice::fee::FeeSchedule;
+use ::invoice::fx::{self, Rates};
+use ::invoice::ledger::Ledger;
+use ::invoice::limits::{self, Limits};
+use ::invoice::risk::{self, Action, Risk};
+use ::invoice::{audit, invoice, lifecycle, parallel, server, statement, stream, validate};
 
-use client::ClientMap;
-use csv_reader::CSV;
+// Exits the application with the provided error
+fn exit(code: Exit, context: &str, err: impl std::fmt::Display) -> ! {
+    eprintln!("{}: {}", context, err);
+    std::process::exit(code as i32);
+}
+
+// Exits the application with the parse code if a lenient load skipped lines
+fn skipped(csv: &CSV) {
+    if !csv.malformed.is_empty() {
+        std::process::exit(Exit::Parse as i32);
+    }
+}
+
+// Exits the application with the rejected code if the limits or the risk rules rejected a record,
+// or a record was not applied as it conflicts with an earlier one
+fn rejected(ledger: &Ledger) {
+    // flagged and held records are applied
+    let risk = ledger
+        .flags
+        .iter()
+        .any(|flag| flag.action == Action::Reject);
+    if !ledger.rejections.is_empty() || risk || !ledger.conflicts.is_empty() {
+        std::process::exit(Exit::Rejected as i32);
+    }
+}
 
 fn main() {
-    // load csv
-    let csv = match CSV::load(env::args().collect()) {
-        Err(err) => {
-            eprintln!("Failed to load csv: {}", err);
-            std::process::exit(1);
+    let cli = match cli::parse(env::args().collect()) {
+        Err(err) => exit(
+            Exit::Usage,
+            "Failed to parse arguments",
+            format!("{}\nRun with --help for the usage", err),
+        ),
+        Ok(cli) => cli,
+    };
+
+    match &cli.command {
+        Command::Help(command) => {
+            print!("{}", cli::help(command.as_deref()));
+            return;
         }
-        Ok(csv) => csv,
+        Command::Version => {
+            println!("invoice {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        _ => {}
+    }
+
+    // load the files of the engine options
+    let mut config = cli.config;
+
+    // --fees <fees.csv>
+    if let Some(path) = &cli.fees {
+        config.fees = match FeeSchedule::load(path) {
+            Err(err) => exit(Exit::of(&*err), "Failed to load fee schedule", err),
+            Ok(schedule) => schedule,
+        };
+    }
+
+    // --limits <limits.csv>
+    if let Some(path) = &cli.limits {
+        config.limits = match Limits::load(path) {
+            Err(err) => exit(Exit::of(&*err), "Failed to load limits", err),
+            Ok(limits) => limits,
+        };
+    }
+
+    // --risk <risk.csv>
+    if let Some(path) = &cli.risk {
+        config.risk = match Risk::load(path) {
+            Err(err) => exit(Exit::of(&*err), "Failed to load risk rules", err),
+            Ok(risk) => risk,
+        };
+    }
+
+    // --rates <rates.csv>
+    if let Some(path) = &cli.rates {
+        config.rates = match Rates::load(path) {
+            Err(err) => exit(Exit::of(&*err), "Failed to load rates", err),
+            Ok(rates) => rates,
+        };
+    }
+
+    // --threads <n>, 0 uses every core
+    let threads = match cli.threads {
+        0 => std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+        threads => threads,
     };
 
-    // create a memory store for clients
-    let mut clients: ClientMap = HashMap::new();
+    // commands that do not process a file
+    match &cli.command {
+        // serve the engine over http
+        Command::Serve(addr) => {
+            println!("Listening on http://{}", addr);
+            if let Err(err) = server::serve(addr, config) {
+                exit(Exit::Io, "Failed to serve", err);
+            }
+            return;
+        }
+        // apply the lines of tcp connections
+        Command::Listen(addr) => {
+            println!("Listening on {}", addr);
+            if let Err(err) = stream::listen(addr, config) {
+                exit(Exit::Io, "Failed to listen", err);
+            }
+            return;
+        }
+        _ => {}
+    }
 
-    // process csv
-    for record in csv.records_vec {
-        let client = transction::process(&record, &clients, &csv.records_map);
+    let path = cli.path.unwrap_or_default();
 
-        clients.insert(client.client, client);
+    // apply the lines of the file one at a time like a connection
+    if let Command::Replay = cli.command {
+        let file = match File::open(&path) {
+            Err(err) => exit(Exit::Io, "Failed to load file", err),
+            Ok(file) => file,
+        };
+        let stdout = io::stdout();
+        match stream::replay(config, BufReader::new(file), stdout.lock()) {
+            Err(err) => exit(Exit::of(&*err), "Failed to replay file", err),
+            Ok((_engine, tally)) if tally.errors > 0 => std::process::exit(Exit::Parse as i32),
+            Ok((_engine, tally)) if tally.rejected > 0 => std::process::exit(Exit::Rejected as i32),
+            Ok(_) => return,
+        }
     }
 
-    // output csv
-    match CSV::output(clients) {
-        Err(err) => {
-            eprintln!("Failed to output csv: {}", err);
-            std::process::exit(1);
+    // apply the records without output and write what was found
+    if let Command::Validate = cli.command {
+        let max_errors = match cli.mode {
+            Mode::Lenient(max_errors) => max_errors,
+            Mode::Strict => None,
+        };
+        match validate::output(&path, cli.input, max_errors, &config) {
+            Err(err) => exit(Exit::of(&*err), "Failed to validate file", err),
+            Ok(summary) if !summary.malformed.is_empty() => std::process::exit(Exit::Parse as i32),
+            Ok(summary) if summary.rejected() => std::process::exit(Exit::Rejected as i32),
+            Ok(summary) if summary.problems() => std::process::exit(Exit::Failure as i32),
+            Ok(_) => return,
         }
-        Ok(()) => (),
+    }
+
+    // load csv
+    let csv = match CSV::open(&path, cli.input, cli.mode) {
+        Err(err) => exit(Exit::of(&*err), "Failed to load csv", err),
+        Ok(csv) => csv,
     };
+
+    // a lenient load skipped these lines
+    for malformed in &csv.malformed {
+        eprintln!("Skipped line {}\n{}\n", malformed.line, malformed);
+    }
+    let process = matches!(cli.command, Command::Process(_));
+
+    // replay the csv and check the invariants after every row
+    if let Command::Audit = cli.command {
+        match audit::audit(&csv, &config) {
+            Err(violation) => {
+                eprint!("{}", violation);
+                std::process::exit(Exit::Failure as i32);
+            }
+            Ok(rows) => println!("Audit passed: {} rows", rows),
+        }
+        skipped(&csv);
+        return;
+    }
+
+    // process csv into a ledger for clients and a memory store for their history
+    let (ledger, history) = parallel::process(&csv, &config, threads);
+
+    // the ledger must always balance
+    if let Err(err) = ledger.check() {
+        exit(Exit::Failure, "Failed to process csv", err);
+    }
+
+    // records with the tx or idempotency key of an earlier one with other contents were not applied
+    for conflict in &ledger.conflicts {
+        eprintln!("Skipped line {}\n{}\n", csv.line(conflict.row), conflict);
+    }
+
+    // output the flagged records apart from the normal output
+    if let Some(path) = cli.flagged {
+        let result = File::create(&path)
+            .map_err(|err| err.into())
+            .and_then(|file| risk::write_csv(&ledger.flags, file));
+        if let Err(err) = result {
+            exit(Exit::Io, "Failed to output flagged records", err);
+        }
+    }
+
+    match cli.command {
+        // output statements
+        Command::Statement(format) => {
+            if let Err(err) = statement::output(&history, format) {
+                exit(Exit::Io, "Failed to output statement", err);
+            }
+        }
+        // output one invoice file per client
+        Command::Invoice(dir, options) => {
+            let invoices = invoice::generate(&history, &options);
+            match invoice::output(&invoices, &options, Path::new(&dir)) {
+                Err(err) => exit(Exit::Io, "Failed to output invoices", err),
+                Ok(paths) => {
+                    for path in paths {
+                        println!("{}", path.display());
+                    }
+                }
+            }
+        }
+        // output the lifecycle of a tx
+        Command::Lifecycle(tx) => {
+            let stdout = io::stdout();
+            if let Err(err) =
+                lifecycle::write_text(tx, &ledger.lifecycles, &csv.records_map, stdout.lock())
+            {
+                exit(Exit::Io, "Failed to output lifecycle", err);
+            }
+        }
+        // output the records rejected by the limits
+        Command::Rejections => {
+            let stdout = io::stdout();
+            if let Err(err) = limits::write_csv(&ledger.rejections, stdout.lock()) {
+                exit(Exit::Io, "Failed to output rejections", err);
+            }
+        }
+        // output the balances
+        Command::Process(format) => {
+            let clients = match cli.base {
+                Some(base) => match fx::report(&ledger.clients, base, &config.rates) {
+                    Err(err) => exit(Exit::Failure, "Failed to convert balances", err),
+                    Ok(clients) => clients,
+                },
+                None => ledger.clients.clone(),
+            };
+            if let Err(err) = CSV::output(clients, format) {
+                exit(Exit::Io, "Failed to output csv", err);
+            }
+        }
+        Command::Validate
+        | Command::Audit
+        | Command::Replay
+        | Command::Serve(_)
+        | Command::Listen(_)
+        | Command::Help(_)
+        | Command::Version => {}
+    }
+
+    // the output is written, the exit code tells whether lines were skipped or records rejected
+    skipped(&csv);
+    if process {
+        rejected(&ledger);
+    }
 }
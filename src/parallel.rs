@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::thread;
+
+use crate::config::Config;
+use crate::csv_reader::{Record, Type, CSV};
+use crate::ledger::Ledger;
+use crate::statement::{self, HistoryMap};
+use crate::transction::{self, Submission};
+
+// Returns true if the records of a client never change another client with the configuration,
+// in which case the clients can be processed apart. Transfers move funds between clients,
+// expired disputes and cleared deposits are applied at the time of any client,
+// and a tx or idempotency key of another client is a resubmission of its record.
+pub fn shardable(csv: &CSV, config: &Config) -> bool {
+    if config.dispute_expiry.is_some() || config.clearing() {
+        return false;
+    }
+
+    let mut owners: HashMap<Submission, u16> = HashMap::new();
+    let shared = csv.records_vec.iter().any(|record| {
+        Submission::of(record).is_some_and(|submission| {
+            *owners.entry(submission).or_insert(record.client) != record.client
+        })
+    });
+    if shared {
+        return false;
+    }
+
+    csv.records_vec
+        .iter()
+        .all(|record| record.r#type != Type::TRANSFER)
+}
+
+// Applies the records in order, returns the ledger and the history of every client
+pub fn serial(csv: &CSV, config: &Config) -> (Ledger, HistoryMap) {
+    let records: Vec<&Record> = csv.records_vec.iter().collect();
+
+    shard(&records, csv, config)
+}
+
+// Applies the records of a shard in order
+fn shard(records: &[&Record], csv: &CSV, config: &Config) -> (Ledger, HistoryMap) {
+    let mut ledger = Ledger::default();
+    let mut history: HistoryMap = HashMap::new();
+
+    for record in records {
+        for (record, applied) in transction::step(record, &mut ledger, &csv.records_map, config) {
+            statement::record(&mut history, &record, &applied);
+        }
+    }
+
+    (ledger, history)
+}
+
+// Applies the records split by client over the given number of threads, the records of a client
+// keep their order. The result is the same as applying them in order, which is done instead
+// if the records can not be split.
+pub fn process(csv: &CSV, config: &Config, threads: usize) -> (Ledger, HistoryMap) {
+    if threads <= 1 || !shardable(csv, config) {
+        return serial(csv, config);
+    }
+
+    let mut shards: Vec<Vec<&Record>> = vec![vec![]; threads];
+    for record in &csv.records_vec {
+        shards[usize::from(record.client) % threads].push(record);
+    }
+
+    let results: Vec<(Ledger, HistoryMap)> = thread::scope(|scope| {
+        let handles: Vec<_> = shards
+            .iter()
+            .map(|records| scope.spawn(move || shard(records, csv, config)))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Failed to join shard"))
+            .collect()
+    });
+
+    let mut ledgers = vec![];
+    let mut history: HistoryMap = HashMap::new();
+    for (ledger, shard) in results {
+        ledgers.push(ledger);
+        history.extend(shard);
+    }
+
+    (Ledger::merge(ledgers), history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::fee::{Fee, FeeSchedule};
+
+    fn records() -> CSV {
+        let mut csv = CSV::default();
+
+        for i in 0..2000u32 {
+            let client = (i % 37 + 1) as u16;
+            let r#type = match i % 7 {
+                0..=2 => Type::DEPOSIT,
+                3 | 4 => Type::WITHDRAWAL,
+                5 => Type::DISPUTE,
+                _ => Type::CHARGEBACK,
+            };
+            // disputes refer to an earlier deposit of the client and chargebacks to a disputed one,
+            // the first ones refer to no tx at all
+            let tx = match r#type {
+                Type::DISPUTE if i >= 185 => i - 185,
+                Type::CHARGEBACK if i >= 333 => i - 333,
+                Type::DISPUTE | Type::CHARGEBACK => i + 10000,
+                _ => i,
+            };
+            let amount = match r#type {
+                Type::DEPOSIT | Type::WITHDRAWAL => Some(f64::from(i % 13) + 0.1),
+                _ => None,
+            };
+            let record = Record {
+                row: i as usize + 1,
+                ..Record::new(r#type, client, tx, amount)
+            };
+            csv.records_vec.push(record.clone());
+            crate::csv_reader::index(&mut csv.records_map, record);
+        }
+
+        csv
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        let mut fees = FeeSchedule::default();
+        fees.insert(Fee {
+            r#type: Type::DEPOSIT,
+            min: None,
+            flat: 0.01,
+            percent: 0.3,
+        });
+        config.fees = fees;
+        config.limits.default.max_amount = Some(11.0);
+
+        config
+    }
+
+    fn statements(history: &HistoryMap) -> String {
+        let mut output = vec![];
+        statement::write_csv(history, &mut output).expect("Failed to write statements");
+
+        String::from_utf8(output).expect("Failed to read statements")
+    }
+
+    fn sorted(ledger: &Ledger) -> Vec<String> {
+        let mut clients: Vec<&Client> = ledger.clients.values().collect();
+        clients.sort_by_key(|client| (client.client, client.currency));
+
+        clients
+            .iter()
+            .map(|client| format!("{:?}", client))
+            .collect()
+    }
+
+    #[test]
+    fn test_process() {
+        let csv = records();
+        let config = config();
+        assert!(shardable(&csv, &config));
+
+        let (serial, serial_history) = serial(&csv, &config);
+        let (parallel, parallel_history) = process(&csv, &config, 4);
+
+        assert_eq!(sorted(&parallel), sorted(&serial));
+        assert_eq!(parallel.journal, serial.journal);
+        assert_eq!(parallel.rejections, serial.rejections);
+        assert!(!parallel.rejections.is_empty());
+        assert!(serial.clients.values().any(|client| client.locked));
+        assert_eq!(statements(&parallel_history), statements(&serial_history));
+        assert!(parallel.check().is_ok());
+    }
+
+    #[test]
+    fn test_shardable() {
+        let mut csv = records();
+        let config = config();
+
+        // a dispute of the tx of another client is ignored
+        csv.records_vec[187].client = 40;
+        assert!(shardable(&csv, &config));
+        let (serial, _history) = serial(&csv, &config);
+        let (parallel, _history) = process(&csv, &config, 4);
+        assert_eq!(sorted(&parallel), sorted(&serial));
+
+        // a transfer
+        csv.records_vec[187].r#type = Type::TRANSFER;
+        csv.records_vec[187].recipient = Some(1);
+        assert!(!shardable(&csv, &config));
+        assert!(!shardable(
+            &records(),
+            &Config {
+                dispute_expiry: Some(chrono::Duration::days(1)),
+                ..Default::default()
+            }
+        ));
+    }
+}
@@ -0,0 +1,324 @@
+use chrono::Duration;
+use csv::{ReaderBuilder, Trim};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+
+use crate::csv_reader::{Record, RecordMap, Type};
+use crate::ledger::Ledger;
+
+// Rules scoring a record before it is applied, the windows are in minutes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    // a deposit or withdrawal after at least count deposits and withdrawals of both types within the window
+    Cycling { count: usize, window: i64 },
+    // a dispute of a client that already disputed at least count transactions
+    RepeatedDisputes { count: usize },
+    // a withdrawal within the window after a deposit
+    QuickWithdrawal { window: i64 },
+}
+
+// What happens to a record with a given score
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Action {
+    #[serde(rename = "allow")]
+    Allow,
+    // applied and reported
+    #[serde(rename = "flag")]
+    Flag,
+    // applied with the funds held and reported
+    #[serde(rename = "hold")]
+    Hold,
+    // ignored and reported
+    #[serde(rename = "reject")]
+    Reject,
+}
+
+// Spec of the risk csv file. A row is either a rule with the score it adds,
+// or an action (flag, hold, reject) with the score from which it is taken.
+#[derive(Debug, Deserialize, Clone)]
+struct Row {
+    rule: String,
+    count: Option<usize>,
+    window: Option<i64>,
+    score: f64,
+}
+
+// Contains the rules with their scores and the scores of the actions
+#[derive(Clone, Debug, Default)]
+pub struct Risk {
+    pub rules: Vec<(Rule, f64)>,
+    pub flag: Option<f64>,
+    pub hold: Option<f64>,
+    pub reject: Option<f64>,
+}
+
+// A record that was not allowed as is
+#[derive(Debug, Clone, Serialize)]
+pub struct Flag {
+    pub row: usize,
+    pub client: u16,
+    pub tx: u32,
+    pub r#type: Type,
+    pub amount: Option<f64>,
+    pub score: f64,
+    pub action: Action,
+    // names of the rules that matched separated by a semicolon
+    pub rules: String,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Rule::Cycling { .. } => "cycling",
+            Rule::RepeatedDisputes { .. } => "repeated-disputes",
+            Rule::QuickWithdrawal { .. } => "quick-withdrawal",
+        };
+
+        f.pad(name)
+    }
+}
+
+impl Rule {
+    // Returns true if the record matches the rule
+    pub fn matches(&self, record: &Record, ledger: &Ledger, records: &RecordMap) -> bool {
+        let activity = ledger
+            .activity
+            .get(&(record.client, record.currency))
+            .map_or(&[][..], |activity| activity);
+
+        match (self, record.r#type, record.timestamp) {
+            (Rule::Cycling { count, window }, Type::DEPOSIT | Type::WITHDRAWAL, Some(now)) => {
+                let recent: Vec<Type> = activity
+                    .iter()
+                    .filter(|movement| now - movement.timestamp < Duration::minutes(*window))
+                    .map(|movement| movement.r#type)
+                    .collect();
+
+                recent.len() >= *count
+                    && recent.contains(&Type::DEPOSIT)
+                    && recent.contains(&Type::WITHDRAWAL)
+            }
+            (Rule::RepeatedDisputes { count }, Type::DISPUTE, _) => {
+                let disputed = ledger
+                    .lifecycles
+                    .keys()
+                    .filter(|tx| **tx != record.tx)
+                    .filter(|tx| records.get(tx).is_some_and(|tx| tx.client == record.client))
+                    .count();
+
+                disputed >= *count
+            }
+            (Rule::QuickWithdrawal { window }, Type::WITHDRAWAL, Some(now)) => activity
+                .iter()
+                .rev()
+                .find(|movement| movement.r#type == Type::DEPOSIT)
+                .is_some_and(|deposit| now - deposit.timestamp < Duration::minutes(*window)),
+            _ => false,
+        }
+    }
+}
+
+impl Risk {
+    // Loads the provided risk csv file
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut risk = Risk::default();
+
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .from_path(path)?;
+
+        for result in rdr.deserialize() {
+            let row: Row = result?;
+            let rule = match (row.rule.as_str(), row.count, row.window) {
+                ("flag", _, _) => {
+                    risk.flag = Some(row.score);
+                    continue;
+                }
+                ("hold", _, _) => {
+                    risk.hold = Some(row.score);
+                    continue;
+                }
+                ("reject", _, _) => {
+                    risk.reject = Some(row.score);
+                    continue;
+                }
+                ("cycling", Some(count), Some(window)) => Rule::Cycling { count, window },
+                ("repeated-disputes", Some(count), _) => Rule::RepeatedDisputes { count },
+                ("quick-withdrawal", _, Some(window)) => Rule::QuickWithdrawal { window },
+                _ => return Err(format!("Error: Invalid risk rule {}", row.rule))?,
+            };
+            risk.rules.push((rule, row.score));
+        }
+
+        Ok(risk)
+    }
+
+    // Returns the score of the record with the rules that matched and the action taken
+    pub fn score(
+        &self,
+        record: &Record,
+        ledger: &Ledger,
+        records: &RecordMap,
+    ) -> (f64, Vec<Rule>, Action) {
+        let matched: Vec<(Rule, f64)> = self
+            .rules
+            .iter()
+            .filter(|(rule, _score)| rule.matches(record, ledger, records))
+            .copied()
+            .collect();
+        let score = matched.iter().map(|(_rule, score)| score).sum();
+
+        // the most severe action the score reaches is taken
+        let action = [
+            (Action::Reject, self.reject),
+            (Action::Hold, self.hold),
+            (Action::Flag, self.flag),
+        ]
+        .iter()
+        .find(|(_action, threshold)| threshold.is_some_and(|threshold| score >= threshold))
+        .map_or(Action::Allow, |(action, _threshold)| *action);
+
+        // only deposits and withdrawals can be held, other records are flagged instead
+        let action = match (action, record.r#type) {
+            (Action::Hold, Type::DEPOSIT | Type::WITHDRAWAL) => Action::Hold,
+            (Action::Hold, _) => Action::Flag,
+            (action, _) => action,
+        };
+
+        (
+            score,
+            matched.iter().map(|(rule, _score)| *rule).collect(),
+            action,
+        )
+    }
+}
+
+// Writes the flagged records as csv
+pub fn write_csv<W: Write>(flags: &[Flag], writer: W) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+
+    for flag in flags {
+        wtr.serialize(flag)?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Movement;
+
+    fn record(r#type: Type, timestamp: &str) -> Record {
+        Record {
+            timestamp: Some(timestamp.parse().expect("Failed to parse timestamp")),
+            ..Record::new(r#type, 1, 9, Some(1.0))
+        }
+    }
+
+    fn ledger() -> Ledger {
+        let mut ledger = Ledger::default();
+        let activity = ledger.activity.entry((1, None)).or_default();
+        for (r#type, timestamp) in [
+            (Type::DEPOSIT, "2021-01-01T10:00:00Z"),
+            (Type::WITHDRAWAL, "2021-01-01T10:05:00Z"),
+            (Type::DEPOSIT, "2021-01-01T10:10:00Z"),
+        ]
+        .iter()
+        {
+            activity.push(Movement {
+                timestamp: timestamp.parse().expect("Failed to parse timestamp"),
+                r#type: *r#type,
+                amount: 1.0,
+            });
+        }
+
+        ledger
+    }
+
+    #[test]
+    fn test_load() {
+        let risk = Risk::load("risk.csv").expect("Failed to load risk rules");
+
+        assert_eq!(risk.rules.len(), 3);
+        assert_eq!(risk.flag, Some(30.0));
+        assert_eq!(risk.reject, Some(100.0));
+    }
+
+    #[test]
+    fn test_matches() {
+        let ledger = ledger();
+        let records = RecordMap::new();
+        let withdrawal = record(Type::WITHDRAWAL, "2021-01-01T10:15:00Z");
+
+        assert!(Rule::Cycling {
+            count: 3,
+            window: 60
+        }
+        .matches(&withdrawal, &ledger, &records));
+        assert!(!Rule::Cycling {
+            count: 4,
+            window: 60
+        }
+        .matches(&withdrawal, &ledger, &records));
+        assert!(Rule::QuickWithdrawal { window: 10 }.matches(&withdrawal, &ledger, &records));
+
+        let later = record(Type::WITHDRAWAL, "2021-01-02T10:15:00Z");
+        assert!(!Rule::QuickWithdrawal { window: 10 }.matches(&later, &ledger, &records));
+    }
+
+    #[test]
+    fn test_score() {
+        let ledger = ledger();
+        let records = RecordMap::new();
+        let risk = Risk {
+            rules: vec![
+                (Rule::QuickWithdrawal { window: 10 }, 30.0),
+                (
+                    Rule::Cycling {
+                        count: 3,
+                        window: 60,
+                    },
+                    40.0,
+                ),
+            ],
+            flag: Some(30.0),
+            hold: Some(60.0),
+            reject: Some(100.0),
+        };
+
+        let (score, rules, action) = risk.score(
+            &record(Type::WITHDRAWAL, "2021-01-01T10:15:00Z"),
+            &ledger,
+            &records,
+        );
+        assert_eq!(score, 70.0);
+        assert_eq!(rules.len(), 2);
+        assert_eq!(action, Action::Hold);
+
+        let (score, _rules, action) = risk.score(
+            &record(Type::DEPOSIT, "2021-01-03T10:15:00Z"),
+            &ledger,
+            &records,
+        );
+        assert_eq!(score, 0.0);
+        assert_eq!(action, Action::Allow);
+
+        let risk = Risk {
+            rules: vec![(Rule::RepeatedDisputes { count: 0 }, 70.0)],
+            ..risk
+        };
+        let (score, _rules, action) = risk.score(
+            &record(Type::DISPUTE, "2021-01-01T10:15:00Z"),
+            &ledger,
+            &records,
+        );
+        assert_eq!(score, 70.0);
+        assert_eq!(action, Action::Flag);
+    }
+}
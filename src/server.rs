@@ -0,0 +1,324 @@
+use serde::Serialize;
+use std::error::Error;
+use tiny_http::{Header, Response, Server};
+
+use crate::client::Client;
+use crate::config::Config;
+use crate::csv_reader::{Record, Status, Type, CSV};
+use crate::currency::Currency;
+use crate::engine::{Engine, Error as EngineError};
+
+// Result of a submitted record
+#[derive(Debug, Serialize)]
+struct Submitted {
+    row: usize,
+    tx: u32,
+    r#type: Type,
+    applied: bool,
+    // the record was submitted before, the result is the one of the first time
+    replayed: bool,
+    client: Client,
+}
+
+// Status of a tx
+#[derive(Debug, Serialize)]
+struct Transaction {
+    tx: u32,
+    r#type: Type,
+    client: u16,
+    amount: Option<f64>,
+    currency: Option<Currency>,
+    status: Status,
+    pending: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Failure {
+    error: String,
+}
+
+// Result of a record of a batch, a record that failed has the status code and error of the failure
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Batched {
+    Submitted(Submitted),
+    Failed {
+        row: usize,
+        tx: u32,
+        status: u16,
+        error: String,
+    },
+}
+
+// Returns the status code of an error of the engine
+fn status(err: &EngineError) -> u16 {
+    match err {
+        EngineError::Conflict(_) => 409,
+        _ => 422,
+    }
+}
+
+// Applies a record and returns its result
+fn submit(engine: &mut Engine, record: Record) -> Result<Submitted, EngineError> {
+    let outcome = engine.apply(record)?;
+
+    Ok(Submitted {
+        row: outcome.record.row,
+        tx: outcome.record.tx,
+        r#type: outcome.record.r#type,
+        applied: outcome.applied.amount.is_some(),
+        replayed: outcome.replayed,
+        client: outcome.applied.after,
+    })
+}
+
+// Returns the status code and json body of a failure
+fn error(status: u16, message: impl ToString) -> (u16, String) {
+    let failure = Failure {
+        error: message.to_string(),
+    };
+
+    (status, serde_json::to_string(&failure).unwrap_or_default())
+}
+
+// Returns the status code and json body of a value
+fn json<T: Serialize>(value: &T) -> (u16, String) {
+    match serde_json::to_string(value) {
+        Err(err) => error(500, err),
+        Ok(body) => (200, body),
+    }
+}
+
+// Handles a request to the engine, returns the status code and the json body.
+//
+// POST /transactions        applies the record in the json body
+// POST /transactions/batch  applies the records of the csv body in order, with a result per record
+// GET  /clients/<id>        balances of the client in every currency
+// GET  /transactions/<tx>   status of the tx
+pub fn handle(engine: &mut Engine, method: &str, url: &str, body: &str) -> (u16, String) {
+    let path = url.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    match (method, segments.as_slice()) {
+        ("POST", ["transactions"]) => {
+            let record: Record = match serde_json::from_str(body) {
+                Err(err) => return error(400, format!("Error: Invalid record, {}", err)),
+                Ok(record) => record,
+            };
+            match submit(engine, record) {
+                Err(err) => error(status(&err), err),
+                Ok(submitted) => json(&submitted),
+            }
+        }
+        ("POST", ["transactions", "batch"]) => {
+            let csv = match CSV::from_reader(body.as_bytes()) {
+                Err(err) => return error(400, err),
+                Ok(csv) => csv,
+            };
+            // a record that fails does not stop the records after it
+            let mut batched = vec![];
+            for record in csv.records_vec {
+                let (row, tx) = (record.row, record.tx);
+                batched.push(match submit(engine, record) {
+                    Err(err) => Batched::Failed {
+                        row,
+                        tx,
+                        status: status(&err),
+                        error: err.to_string(),
+                    },
+                    Ok(submitted) => Batched::Submitted(submitted),
+                });
+            }
+            json(&batched)
+        }
+        ("GET", ["clients", id]) => {
+            let id: u16 = match id.parse() {
+                Err(_) => return error(400, format!("Error: Invalid client {}", id)),
+                Ok(id) => id,
+            };
+            let mut clients: Vec<Client> = engine
+                .ledger
+                .clients
+                .values()
+                .filter(|client| client.client == id)
+                .copied()
+                .collect();
+            if clients.is_empty() {
+                return error(404, format!("Error: Unknown client {}", id));
+            }
+            clients.sort_by_key(|client| client.currency);
+            json(&clients)
+        }
+        ("GET", ["transactions", tx]) => {
+            let tx: u32 = match tx.parse() {
+                Err(_) => return error(400, format!("Error: Invalid tx {}", tx)),
+                Ok(tx) => tx,
+            };
+            let record = match engine.records.get(&tx) {
+                None => return error(404, format!("Error: Unknown tx {}", tx)),
+                Some(record) => record,
+            };
+            json(&Transaction {
+                tx,
+                r#type: record.r#type,
+                client: record.client,
+                amount: record.amount,
+                currency: record.currency,
+                status: engine
+                    .ledger
+                    .lifecycles
+                    .get(&tx)
+                    .map_or(Status::NONE, |lifecycle| lifecycle.status),
+                pending: engine.ledger.pending.contains_key(&tx),
+            })
+        }
+        _ => error(404, format!("Error: Unknown endpoint {} {}", method, path)),
+    }
+}
+
+// Answers the requests of the server one at a time until it is closed
+pub fn run(server: &Server, engine: &mut Engine) {
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("Failed to create header");
+
+    for mut request in server.incoming_requests() {
+        let mut body = String::new();
+        let (status, body) = match request.as_reader().read_to_string(&mut body) {
+            Err(err) => error(400, err),
+            Ok(_) => handle(engine, request.method().as_str(), request.url(), &body),
+        };
+
+        let response = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header.clone());
+        // a client that went away does not stop the server
+        let _ = request.respond(response);
+    }
+}
+
+// Listens on the address and serves the engine with the given configuration
+pub fn serve(addr: &str, config: Config) -> Result<(), Box<dyn Error>> {
+    let server = Server::http(addr).map_err(|err| format!("Error: {}", err))?;
+    run(&server, &mut Engine::new(config));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_handle() {
+        let mut engine = Engine::default();
+
+        let (status, body) = handle(
+            &mut engine,
+            "POST",
+            "/transactions",
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 10.0}"#,
+        );
+        assert_eq!(status, 200);
+        assert!(body.contains(r#""applied":true"#));
+        assert!(body.contains(r#""available":10.0"#));
+
+        // the same record again returns the first result, another amount for the tx is a conflict
+        let (status, body) = handle(
+            &mut engine,
+            "POST",
+            "/transactions",
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 10.0}"#,
+        );
+        assert_eq!(status, 200);
+        assert!(body.contains(r#""replayed":true"#));
+        let (status, _) = handle(
+            &mut engine,
+            "POST",
+            "/transactions",
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 20.0}"#,
+        );
+        assert_eq!(status, 409);
+
+        let (status, body) = handle(
+            &mut engine,
+            "POST",
+            "/transactions/batch",
+            "type,client,tx,amount\ndeposit,1,2,4.0\ndispute,1,1,\nwithdrawal,1,3,50.0\n",
+        );
+        assert_eq!(status, 200);
+        let submitted: Vec<serde_json::Value> =
+            serde_json::from_str(&body).expect("Failed to parse body");
+        assert_eq!(submitted.len(), 3);
+        assert_eq!(submitted[1]["row"], 3);
+        assert_eq!(submitted[2]["applied"], false);
+
+        let (status, body) = handle(&mut engine, "GET", "/clients/1", "");
+        assert_eq!(status, 200);
+        assert!(body.contains(r#""held":10.0"#));
+
+        let (status, body) = handle(&mut engine, "GET", "/transactions/1", "");
+        assert_eq!(status, 200);
+        assert!(body.contains(r#""status":"DISPUTED""#));
+
+        // a failing record is reported with the records around it
+        let (status, body) = handle(
+            &mut engine,
+            "POST",
+            "/transactions/batch",
+            "type,client,tx,amount\ndeposit,1,4,1.0\ndeposit,1,2,5.0\ndeposit,1,5,1.0\n",
+        );
+        assert_eq!(status, 200);
+        let submitted: Vec<serde_json::Value> =
+            serde_json::from_str(&body).expect("Failed to parse body");
+        assert_eq!(submitted.len(), 3);
+        assert_eq!(submitted[0]["applied"], true);
+        assert_eq!(submitted[1]["status"], 409);
+        assert_eq!(submitted[1]["tx"], 2);
+        assert!(submitted[1]["error"].is_string());
+        assert_eq!(submitted[2]["applied"], true);
+
+        assert_eq!(handle(&mut engine, "GET", "/clients/2", "").0, 404);
+        assert_eq!(handle(&mut engine, "GET", "/clients/x", "").0, 400);
+        assert_eq!(handle(&mut engine, "POST", "/transactions", "{}").0, 400);
+        assert_eq!(handle(&mut engine, "DELETE", "/transactions/1", "").0, 404);
+    }
+
+    #[test]
+    fn test_serve() {
+        let server = Server::http("127.0.0.1:0").expect("Failed to start server");
+        let addr = server
+            .server_addr()
+            .to_ip()
+            .expect("Failed to get server address");
+        std::thread::spawn(move || run(&server, &mut Engine::default()));
+
+        let request = |request: String| {
+            let mut stream = TcpStream::connect(addr).expect("Failed to connect");
+            stream
+                .write_all(request.as_bytes())
+                .expect("Failed to send request");
+            let mut response = String::new();
+            stream
+                .read_to_string(&mut response)
+                .expect("Failed to read response");
+            response
+        };
+
+        let body = r#"{"type": "deposit", "client": 7, "tx": 1, "amount": 2.5}"#;
+        let response = request(format!(
+            "POST /transactions HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        let response = request(
+            "GET /clients/7 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n".to_string(),
+        );
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains(r#""total":2.5"#));
+    }
+}
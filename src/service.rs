@@ -0,0 +1,158 @@
+use std::thread::{self, JoinHandle};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::client::Client;
+use crate::csv_reader::Record;
+use crate::engine::{Engine, Error};
+
+// A record waiting to be applied with where its result goes
+type Job = (Record, oneshot::Sender<Result<Client, Error>>);
+
+// Async handle of an engine running on its own thread. Records are queued and applied one at a time
+// in the order they were queued, so the records of a client are never applied concurrently.
+// The handle can be cloned, the engine stops once every handle is dropped.
+#[derive(Debug, Clone)]
+pub struct Service {
+    sender: mpsc::Sender<Job>,
+}
+
+impl Service {
+    // Starts the engine with a queue of the given capacity, returns the handle and the thread
+    // of the engine which returns the engine once it stops
+    pub fn start(engine: Engine, capacity: usize) -> (Service, JoinHandle<Engine>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let worker = thread::spawn(move || work(engine, receiver));
+
+        (Service { sender }, worker)
+    }
+
+    // Applies the record and returns the client afterwards, waits for room in the queue if it is full
+    pub async fn submit(&self, record: Record) -> Result<Client, Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send((record, sender))
+            .await
+            .map_err(|_| Error::Closed)?;
+
+        receiver.await.map_err(|_| Error::Closed)?
+    }
+
+    // Applies the record and returns the client afterwards, fails right away if the queue is full
+    pub async fn try_submit(&self, record: Record) -> Result<Client, Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .try_send((record, sender))
+            .map_err(|err| match err {
+                mpsc::error::TrySendError::Full(_) => Error::Full,
+                mpsc::error::TrySendError::Closed(_) => Error::Closed,
+            })?;
+
+        receiver.await.map_err(|_| Error::Closed)?
+    }
+}
+
+// Applies the queued records until every handle is dropped
+fn work(mut engine: Engine, mut receiver: mpsc::Receiver<Job>) -> Engine {
+    while let Some((record, sender)) = receiver.blocking_recv() {
+        let result = engine.client(record);
+
+        // a caller that stopped waiting does not stop the engine
+        let _ = sender.send(result);
+    }
+
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_reader::Type;
+
+    #[tokio::test]
+    async fn test_submit() {
+        let mut engine = Engine::default();
+        engine.config.limits.default.max_amount = Some(100.0);
+        let (service, worker) = Service::start(engine, 4);
+
+        let client = service
+            .submit(Record::new(Type::DEPOSIT, 1, 1, Some(10.0)))
+            .await
+            .expect("Failed to submit record");
+        assert_eq!(client.available, 10.0);
+
+        assert_eq!(
+            service
+                .submit(Record::new(Type::WITHDRAWAL, 1, 2, Some(50.0)))
+                .await
+                .err(),
+            Some(Error::Rejected(None))
+        );
+        assert_eq!(
+            service
+                .submit(Record::new(Type::DEPOSIT, 1, 3, Some(500.0)))
+                .await
+                .err(),
+            Some(Error::Rejected(Some("max-amount".to_string())))
+        );
+
+        drop(service);
+        let engine = worker.join().expect("Failed to join engine");
+        assert_eq!(engine.ledger.client(1, None).total, 10.0);
+    }
+
+    #[tokio::test]
+    async fn test_submit_concurrently() {
+        let (service, worker) = Service::start(Engine::default(), 2);
+
+        // more submissions than the queue holds wait for room instead of failing
+        let submissions = (0..50u32).map(|tx| {
+            let service = service.clone();
+            async move {
+                service
+                    .submit(Record::new(
+                        Type::DEPOSIT,
+                        (tx % 5) as u16 + 1,
+                        tx,
+                        Some(1.0),
+                    ))
+                    .await
+            }
+        });
+        let mut results = vec![];
+        for submission in submissions {
+            results.push(tokio::spawn(submission));
+        }
+        for result in results {
+            assert!(result.await.expect("Failed to join submission").is_ok());
+        }
+
+        drop(service);
+        let engine = worker.join().expect("Failed to join engine");
+        for client in 1..=5 {
+            assert_eq!(engine.ledger.client(client, None).total, 10.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_try_submit_full() {
+        let (sender, _receiver) = mpsc::channel(1);
+        let service = Service { sender };
+
+        // nothing takes from the queue, the first record fills it
+        let pending = service.clone();
+        tokio::spawn(async move {
+            pending
+                .submit(Record::new(Type::DEPOSIT, 1, 1, Some(1.0)))
+                .await
+        });
+        tokio::task::yield_now().await;
+
+        assert_eq!(
+            service
+                .try_submit(Record::new(Type::DEPOSIT, 1, 2, Some(1.0)))
+                .await
+                .err(),
+            Some(Error::Full)
+        );
+    }
+}
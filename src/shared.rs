@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::client::Client;
+use crate::config::Config;
+use crate::csv_reader::Record;
+use crate::currency::Currency;
+use crate::engine::{Engine, Error};
+use crate::ledger::Ledger;
+use crate::transction::{self, Submission};
+
+// Handle to engines shared by threads. The clients are split over shards by id, each shard
+// being an engine with a lock of its own, so records of clients of different shards are applied
+// at the same time and the records of a client are applied one at a time. Clones share the engines.
+//
+// A shard only knows the transactions of its own clients. A transfer to a client of another shard,
+// or a dispute, resolve or chargeback of one, locks both shards in the order of their index and
+// hands the funds of the recipient over to the shard of the sender while the record is applied.
+// A tx or idempotency key submitted before by a client of another shard is a conflict,
+// so the result does not depend on the number of shards.
+// The dispute expiry and clearing periods apply at the records of the clients of the same shard,
+// so a configuration with either always has a single shard.
+#[derive(Debug, Clone)]
+pub struct Shared {
+    shards: Arc<Vec<Mutex<Engine>>>,
+    // client of every tx and idempotency key submitted so far
+    owners: Arc<Mutex<HashMap<Submission, u16>>>,
+}
+
+impl Shared {
+    pub fn new(config: Config, shards: usize) -> Self {
+        let shards = match config.dispute_expiry.is_some() || config.clearing() {
+            true => 1,
+            false => shards.max(1),
+        };
+
+        Shared {
+            shards: Arc::new(
+                (0..shards)
+                    .map(|_| Mutex::new(Engine::new(config.clone())))
+                    .collect(),
+            ),
+            owners: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Returns the index of the shard of a client
+    fn index(&self, client: u16) -> usize {
+        usize::from(client) % self.shards.len()
+    }
+
+    // Returns the locked engine of a shard
+    fn lock(&self, shard: usize) -> MutexGuard<'_, Engine> {
+        self.shards[shard].lock().expect("Failed to lock engine")
+    }
+
+    // Returns the conflict of a record with the tx or idempotency key a client of another shard
+    // submitted before, which its own shard does not know, and keeps the client of the ones it submits
+    fn conflict(&self, record: &Record) -> Option<Error> {
+        let submission = Submission::of(record)?;
+        let mut owners = self.owners.lock().expect("Failed to lock owners");
+        let owner = *owners.entry(submission.clone()).or_insert(record.client);
+
+        match owner != record.client && self.index(owner) != self.index(record.client) {
+            true => Some(Error::Conflict(submission.to_string())),
+            false => None,
+        }
+    }
+
+    // Applies the record while the shard of its client is locked, so reading, updating and
+    // storing the client happens at once. Returns the client afterwards or why it was not changed.
+    pub fn apply(&self, record: Record) -> Result<Client, Error> {
+        if let Some(conflict) = self.conflict(&record) {
+            return Err(conflict);
+        }
+
+        // a record that changes a client of another shard as well needs both shards
+        let shard = self.index(record.client);
+        let mut engine = self.lock(shard);
+        let counterparty = transction::counterparty(&record, &engine.records)
+            .map(|(id, _currency)| id)
+            .filter(|id| self.index(*id) != shard);
+        let id = match counterparty {
+            None => return engine.client(record),
+            Some(id) => id,
+        };
+        drop(engine);
+
+        // the shards are locked in order, so threads locking the same two shards wait for each other
+        // instead of holding a shard the other one waits for
+        let other = self.index(id);
+        let (mut engine, mut counterparty) = match shard < other {
+            true => {
+                let engine = self.lock(shard);
+                (engine, self.lock(other))
+            }
+            false => {
+                let counterparty = self.lock(other);
+                (self.lock(shard), counterparty)
+            }
+        };
+
+        let tx = record.tx;
+        counterparty.ledger.hand_over(id, tx, &mut engine.ledger);
+        let result = engine.client(record);
+        engine.ledger.hand_over(id, tx, &mut counterparty.ledger);
+
+        result
+    }
+
+    // Returns the client with the given id and currency
+    pub fn client(&self, id: u16, currency: Option<Currency>) -> Client {
+        self.lock(self.index(id)).ledger.client(id, currency)
+    }
+
+    // Returns a copy of the ledgers of every shard as one ledger
+    pub fn ledger(&self) -> Ledger {
+        let ledgers = self
+            .shards
+            .iter()
+            .map(|shard| shard.lock().expect("Failed to lock engine").ledger.clone())
+            .collect();
+
+        Ledger::merge(ledgers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_reader::Type;
+    use std::thread;
+
+    fn shared<T: Send + Sync>(_handle: &T) {}
+
+    #[test]
+    fn test_apply_concurrently() {
+        let mut config = Config::default();
+        config.fees.insert(crate::fee::Fee {
+            r#type: Type::DEPOSIT,
+            min: None,
+            flat: 0.5,
+            percent: 0.0,
+        });
+        let handle = Shared::new(config, 4);
+        shared(&handle);
+
+        // every thread deposits and withdraws for every client
+        let threads: Vec<_> = (0..8u32)
+            .map(|thread| {
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    for i in 0..100u32 {
+                        let client = (i % 10) as u16 + 1;
+                        let tx = thread * 1000 + i;
+                        handle
+                            .apply(Record::new(Type::DEPOSIT, client, tx, Some(2.0)))
+                            .expect("Failed to apply deposit");
+                        handle
+                            .apply(Record::new(Type::WITHDRAWAL, client, tx + 500, Some(1.0)))
+                            .expect("Failed to apply withdrawal");
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().expect("Failed to join thread");
+        }
+
+        for client in 1..=10 {
+            assert_eq!(handle.client(client, None).total, 8.0 * 10.0 * 0.5);
+        }
+        let ledger = handle.ledger();
+        assert_eq!(ledger.fees(None), 800.0 * 0.5);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn test_apply_transfer() {
+        let transfer = |tx, recipient| Record {
+            recipient: Some(recipient),
+            ..Record::new(Type::TRANSFER, 1, tx, Some(4.0))
+        };
+        let records = [
+            Record::new(Type::DEPOSIT, 1, 1, Some(10.0)),
+            Record::new(Type::DEPOSIT, 2, 2, Some(5.0)),
+            transfer(3, 3),
+            transfer(4, 2),
+            Record::new(Type::DISPUTE, 1, 4, None),
+            Record::new(Type::DEPOSIT, 4, 2, Some(5.0)),
+            Record::new(Type::WITHDRAWAL, 1, 5, Some(50.0)),
+            Record::new(Type::DISPUTE, 2, 1, None),
+            Record::new(Type::DEPOSIT, 3, 2, Some(1.0)),
+            Record::new(Type::CHARGEBACK, 1, 4, None),
+            transfer(6, 2),
+        ];
+
+        // the same records give the same result with one shard or more
+        let clients = |shards| {
+            let handle = Shared::new(Config::default(), shards);
+            let errors: Vec<Option<Error>> = records
+                .iter()
+                .map(|record| handle.apply(record.clone()).err())
+                .collect();
+            let balances: Vec<(f64, f64, bool)> = (1..=4)
+                .map(|id| handle.client(id, None))
+                .map(|client| (client.available, client.held, client.locked))
+                .collect();
+
+            // the shards stay apart, the merged ledger has the same balances
+            let ledger = handle.ledger();
+            assert!(ledger.check().is_ok());
+            for (id, (available, held, _locked)) in (1..=4).zip(&balances) {
+                let client = ledger.client(id, None);
+                assert_eq!((client.available, client.held), (*available, *held));
+            }
+            if shards > 1 {
+                assert_eq!(handle.lock(1).ledger.client(2, None).total, 0.0);
+            }
+
+            (errors, balances)
+        };
+        let (errors, balances) = clients(2);
+        assert_eq!((errors.clone(), balances.clone()), clients(1));
+
+        assert_eq!(errors[3], None);
+        assert!(matches!(errors[5], Some(Error::Conflict(_))));
+        assert_eq!(errors[6], Some(Error::Rejected(None)));
+        assert_eq!(errors[7], Some(Error::Rejected(None)));
+        // a tx of a client of another shard
+        assert!(matches!(errors[8], Some(Error::Conflict(_))));
+        assert_eq!(errors[9], None);
+        // the recipient is locked in the shard of the sender as well
+        assert_eq!(errors[10], Some(Error::Rejected(None)));
+        assert_eq!(
+            balances,
+            vec![
+                (6.0, 0.0, false),
+                (5.0, 0.0, true),
+                (4.0, 0.0, false),
+                (0.0, 0.0, false)
+            ]
+        );
+    }
+}
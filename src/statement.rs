@@ -0,0 +1,373 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::client::{Client, ClientKey};
+use crate::csv_reader::{Record, Status, Type};
+use crate::currency::Currency;
+use crate::transction::Applied;
+
+// Create a type for easier fn definitions
+pub type HistoryMap = HashMap<ClientKey, History>;
+
+// Applied transactions of a single client in a single currency
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    pub opening: Client,
+    pub entries: Vec<Entry>,
+}
+
+// A single applied transaction and the balances it resulted in
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub r#type: Type,
+    pub tx: u32,
+    pub amount: Option<f64>,
+    pub fee: f64,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+    pub status: Status,
+}
+
+// Output formats of a statement
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Text,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "text" => Ok(Format::Text),
+            _ => Err(format!("Error: Unknown statement format {}", s)),
+        }
+    }
+}
+
+// Spec of a row in the csv statement
+#[derive(Debug, Serialize)]
+struct Line {
+    client: u16,
+    currency: Option<Currency>,
+    kind: &'static str,
+    r#type: Option<Type>,
+    tx: Option<u32>,
+    amount: Option<f64>,
+    fee: Option<f64>,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+    status: Option<Status>,
+}
+
+impl History {
+    // Returns true if the entry is part of a dispute
+    fn is_dispute(entry: &Entry) -> bool {
+        matches!(
+            entry.r#type,
+            Type::DISPUTE | Type::RESOLVE | Type::CHARGEBACK
+        )
+    }
+
+    // Returns the balances after the last applied transaction
+    pub fn closing(&self) -> Client {
+        match self.entries.last() {
+            Some(entry) => Client {
+                client: self.opening.client,
+                currency: self.opening.currency,
+                available: entry.available,
+                held: entry.held,
+                total: entry.total,
+                locked: entry.locked,
+            },
+            None => self.opening,
+        }
+    }
+}
+
+// Adds the applied record and its fee to the client history, ignored records are not added
+pub fn record(history: &mut HistoryMap, record: &Record, applied: &Applied) {
+    // ignored transactions do not change the client
+    if applied.amount.is_none() {
+        return;
+    }
+
+    let status = match record.r#type {
+        Type::DEPOSIT
+        | Type::WITHDRAWAL
+        | Type::CONVERT
+        | Type::TRANSFER
+        | Type::CLEAR
+        | Type::RELEASE
+        | Type::REJECT => Status::NONE,
+        Type::DISPUTE => Status::DISPUTED,
+        Type::RESOLVE => Status::RESOLVED,
+        Type::CHARGEBACK => Status::CHARGEBACKED,
+    };
+
+    let after = applied.after;
+    let entries = history
+        .entry((after.client, after.currency))
+        .or_insert_with(|| History {
+            opening: Client {
+                client: after.client,
+                currency: after.currency,
+                ..applied.before
+            },
+            entries: vec![],
+        });
+
+    entries.entries.push(Entry {
+        r#type: record.r#type,
+        tx: record.tx,
+        amount: applied.amount,
+        fee: applied.fee,
+        available: after.available,
+        held: after.held,
+        total: after.total,
+        locked: after.locked,
+        status,
+    });
+}
+
+// Writes the statement of every client to the stdout
+pub fn output(history: &HistoryMap, format: Format) -> Result<(), Box<dyn Error>> {
+    let stdout = io::stdout();
+
+    match format {
+        Format::Csv => write_csv(history, stdout.lock()),
+        Format::Text => write_text(history, stdout.lock()),
+    }
+}
+
+// Returns the client ids and currencies in ascending order
+pub fn sorted(history: &HistoryMap) -> Vec<ClientKey> {
+    let mut ids: Vec<ClientKey> = history.keys().copied().collect();
+    ids.sort_unstable();
+
+    ids
+}
+
+// Writes the statements as csv
+pub fn write_csv<W: Write>(history: &HistoryMap, writer: W) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+
+    for (id, currency) in sorted(history) {
+        let client = &history[&(id, currency)];
+
+        let opening = client.opening;
+        wtr.serialize(Line {
+            client: id,
+            currency,
+            kind: "opening",
+            r#type: None,
+            tx: None,
+            amount: None,
+            fee: None,
+            available: opening.available,
+            held: opening.held,
+            total: opening.total,
+            locked: opening.locked,
+            status: None,
+        })?;
+
+        for entry in &client.entries {
+            let kind = match History::is_dispute(entry) {
+                true => "dispute",
+                false => "movement",
+            };
+
+            wtr.serialize(Line {
+                client: id,
+                currency,
+                kind,
+                r#type: Some(entry.r#type),
+                tx: Some(entry.tx),
+                amount: entry.amount,
+                fee: Some(entry.fee),
+                available: entry.available,
+                held: entry.held,
+                total: entry.total,
+                locked: entry.locked,
+                status: Some(entry.status),
+            })?;
+        }
+
+        let closing = client.closing();
+        wtr.serialize(Line {
+            client: id,
+            currency,
+            kind: "closing",
+            r#type: None,
+            tx: None,
+            amount: None,
+            fee: None,
+            available: closing.available,
+            held: closing.held,
+            total: closing.total,
+            locked: closing.locked,
+            status: None,
+        })?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+// Writes the statements as plain text
+pub fn write_text<W: Write>(history: &HistoryMap, mut writer: W) -> Result<(), Box<dyn Error>> {
+    for (id, currency) in sorted(history) {
+        let client = &history[&(id, currency)];
+
+        match currency {
+            Some(currency) => writeln!(writer, "Statement for client {} in {}", id, currency)?,
+            None => writeln!(writer, "Statement for client {}", id)?,
+        }
+        writeln!(writer)?;
+
+        let opening = client.opening;
+        writeln!(
+            writer,
+            "{:<45} {:>14.4} {:>14.4} {:>14.4}",
+            "Opening balance", opening.available, opening.held, opening.total
+        )?;
+        writeln!(writer)?;
+
+        writeln!(
+            writer,
+            "{:<12} {:>10} {:>10} {:>10} {:>14} {:>14} {:>14}  status",
+            "type", "tx", "amount", "fee", "available", "held", "total"
+        )?;
+        for entry in &client.entries {
+            let line = format!(
+                "{:<12} {:>10} {:>10.4} {:>10.4} {:>14.4} {:>14.4} {:>14.4}  {}",
+                entry.r#type,
+                entry.tx,
+                entry.amount.unwrap_or(0.0),
+                entry.fee,
+                entry.available,
+                entry.held,
+                entry.total,
+                entry.status
+            );
+            writeln!(writer, "{}", line.trim_end())?;
+        }
+        writeln!(writer)?;
+
+        let disputes: Vec<&Entry> = client
+            .entries
+            .iter()
+            .filter(|entry| History::is_dispute(entry))
+            .collect();
+        if !disputes.is_empty() {
+            writeln!(writer, "Disputes")?;
+            for entry in disputes {
+                writeln!(
+                    writer,
+                    "  tx {:<10} {:<12} {:>10.4}  {}",
+                    entry.tx,
+                    entry.r#type,
+                    entry.amount.unwrap_or(0.0),
+                    entry.status
+                )?;
+            }
+            writeln!(writer)?;
+        }
+
+        let closing = client.closing();
+        writeln!(
+            writer,
+            "{:<45} {:>14.4} {:>14.4} {:>14.4}{}",
+            "Closing balance",
+            closing.available,
+            closing.held,
+            closing.total,
+            if closing.locked { "  locked" } else { "" }
+        )?;
+        writeln!(writer)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::csv_reader::CSV;
+    use crate::ledger::Ledger;
+    use crate::transction;
+
+    fn history() -> HistoryMap {
+        let csv = CSV::load("sample.csv").expect("Failed load csv file");
+        let mut ledger = Ledger::default();
+        let mut history: HistoryMap = HashMap::new();
+
+        for record in csv.records_vec {
+            let applied =
+                transction::apply(&record, &mut ledger, &csv.records_map, &Config::default());
+            self::record(&mut history, &record, &applied);
+        }
+
+        history
+    }
+
+    #[test]
+    fn test_history() {
+        let history = history();
+        let client = &history[&(3, None)];
+
+        assert_eq!(client.opening.total, 0.0);
+        assert_eq!(client.entries.len(), 5);
+        assert_eq!(client.entries[3].r#type, Type::DISPUTE);
+        assert_eq!(client.entries[3].amount, Some(5.0));
+        assert_eq!(client.entries[4].status, Status::RESOLVED);
+        assert_eq!(client.closing().total, 15.0);
+    }
+
+    #[test]
+    fn test_ignored_transactions() {
+        let history = history();
+
+        // withdrawal of client 2 exceeds the available funds
+        assert_eq!(history[&(2, None)].entries.len(), 1);
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let history = history();
+        let mut buffer = vec![];
+
+        write_csv(&history, &mut buffer).expect("Failed to write statement");
+        let output = String::from_utf8(buffer).expect("Failed to read statement");
+
+        assert!(output.starts_with("client,currency,kind,type,tx,amount,fee"));
+        assert!(output.contains("3,,dispute,dispute,9,5.0,0.0,15.0,5.0,20.0,false,DISPUTED"));
+        assert!(output.contains("4,,closing,,,,,20.0,0.0,20.0,true,"));
+    }
+
+    #[test]
+    fn test_write_text() {
+        let history = history();
+        let mut buffer = vec![];
+
+        write_text(&history, &mut buffer).expect("Failed to write statement");
+        let output = String::from_utf8(buffer).expect("Failed to read statement");
+
+        assert!(output.contains("Statement for client 4"));
+        assert!(output.contains("Disputes"));
+        assert!(output.contains("locked"));
+    }
+}
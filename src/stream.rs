@@ -0,0 +1,319 @@
+use csv::{ReaderBuilder, StringRecord, Trim};
+use serde::Serialize;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::config::Config;
+use crate::csv_reader::Record;
+use crate::engine::{Engine, Error as EngineError};
+
+// Columns of csv lines on a connection that did not send a header
+const COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+// What happened to a line
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Status {
+    // the line set the columns of the csv lines after it
+    #[serde(rename = "header")]
+    Header,
+    #[serde(rename = "applied")]
+    Applied,
+    // applied through the engine which ignored it
+    #[serde(rename = "rejected")]
+    Rejected,
+    // the line could not be parsed or applied
+    #[serde(rename = "error")]
+    Error,
+}
+
+// Acknowledgement of a line, line numbers start at 1 for every connection
+#[derive(Debug, Clone, Serialize)]
+pub struct Ack {
+    pub line: usize,
+    pub tx: Option<u32>,
+    pub status: Status,
+    // rejection reason of the limits or the risk rules, or the error
+    pub reason: Option<String>,
+    // the line was submitted before, the status is the one of the first time
+    pub replayed: bool,
+}
+
+// Parses a line of json, or of csv with the given columns
+fn parse(line: &str, columns: &StringRecord) -> Result<Record, Box<dyn Error>> {
+    if line.starts_with('{') {
+        return Ok(serde_json::from_str(line)?);
+    }
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .trim(Trim::All)
+        .from_reader(line.as_bytes());
+    match rdr.records().next() {
+        Some(row) => Ok(row?.deserialize(Some(columns))?),
+        None => Err("Error: Empty line")?,
+    }
+}
+
+// Number of lines of every status that is not a header. Rejected are the lines rejected by the limits
+// or the risk rules and the conflicts, ignored the other lines the engine did not apply.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Tally {
+    pub applied: usize,
+    pub rejected: usize,
+    pub ignored: usize,
+    pub errors: usize,
+}
+
+// Applies the lines of a connection through the shared engine as they arrive and acknowledges
+// every line with a json line. A csv line starting with `type` sets the columns of the csv lines
+// after it.
+pub fn handle<R: BufRead, W: Write>(
+    engine: &Mutex<Engine>,
+    reader: R,
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    acknowledge(engine, reader, writer).map(|_tally| ())
+}
+
+// Applies the lines of a file through a new engine like the lines of a connection,
+// returns the engine afterwards and the number of lines of every status
+pub fn replay<R: BufRead, W: Write>(
+    config: Config,
+    reader: R,
+    writer: W,
+) -> Result<(Engine, Tally), Box<dyn Error>> {
+    let engine = Mutex::new(Engine::new(config));
+    let tally = acknowledge(&engine, reader, writer)?;
+
+    Ok((engine.into_inner().expect("Failed to lock engine"), tally))
+}
+
+// Applies and acknowledges every line, returns the number of lines of every status
+fn acknowledge<R: BufRead, W: Write>(
+    engine: &Mutex<Engine>,
+    reader: R,
+    mut writer: W,
+) -> Result<Tally, Box<dyn Error>> {
+    let mut tally = Tally::default();
+    let mut columns = StringRecord::from(COLUMNS.to_vec());
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut conflict = false;
+
+        let ack = if line.starts_with("type") {
+            columns = line.split(',').map(str::trim).collect();
+            Ack {
+                line: i + 1,
+                tx: None,
+                status: Status::Header,
+                reason: None,
+                replayed: false,
+            }
+        } else {
+            match parse(line, &columns) {
+                Err(err) => Ack {
+                    line: i + 1,
+                    tx: None,
+                    status: Status::Error,
+                    reason: Some(err.to_string()),
+                    replayed: false,
+                },
+                Ok(record) => {
+                    let tx = Some(record.tx);
+                    // the engine is locked for a single record, lines are applied in arrival order
+                    let mut engine = engine.lock().expect("Failed to lock engine");
+                    match engine.apply(record) {
+                        Err(err) => {
+                            conflict = matches!(err, EngineError::Conflict(_));
+                            Ack {
+                                line: i + 1,
+                                tx,
+                                status: Status::Error,
+                                reason: Some(err.to_string()),
+                                replayed: false,
+                            }
+                        }
+                        Ok(outcome) => match outcome.applied.amount {
+                            Some(_) => Ack {
+                                line: i + 1,
+                                tx,
+                                status: Status::Applied,
+                                reason: None,
+                                replayed: outcome.replayed,
+                            },
+                            None => Ack {
+                                line: i + 1,
+                                tx,
+                                status: Status::Rejected,
+                                reason: engine.reason(outcome.record.row),
+                                replayed: outcome.replayed,
+                            },
+                        },
+                    }
+                }
+            }
+        };
+
+        match (ack.status, &ack.reason) {
+            (Status::Header, _) => {}
+            (Status::Applied, _) => tally.applied += 1,
+            (Status::Rejected, Some(_)) => tally.rejected += 1,
+            (Status::Rejected, None) => tally.ignored += 1,
+            (Status::Error, _) if conflict => tally.rejected += 1,
+            (Status::Error, _) => tally.errors += 1,
+        }
+
+        serde_json::to_writer(&mut writer, &ack)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+
+    Ok(tally)
+}
+
+// Accepts connections until the listener fails, every connection is handled on its own thread
+pub fn run(listener: TcpListener, engine: Arc<Mutex<Engine>>) -> Result<(), Box<dyn Error>> {
+    for stream in listener.incoming() {
+        let stream: TcpStream = stream?;
+        let engine = Arc::clone(&engine);
+
+        thread::spawn(move || {
+            let reader = match stream.try_clone() {
+                Err(err) => return eprintln!("Failed to read connection: {}", err),
+                Ok(reader) => BufReader::new(reader),
+            };
+            // a connection that went away does not stop the listener
+            if let Err(err) = handle(&engine, reader, stream) {
+                eprintln!("Failed to handle connection: {}", err);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+// Listens on the address and applies the lines of every connection through one engine
+pub fn listen(addr: &str, config: Config) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(addr)?;
+    run(listener, Arc::new(Mutex::new(Engine::new(config))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acks(engine: &Mutex<Engine>, input: &str) -> Vec<serde_json::Value> {
+        let mut output = vec![];
+        handle(engine, input.as_bytes(), &mut output).expect("Failed to handle input");
+
+        String::from_utf8(output)
+            .expect("Failed to read output")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Failed to parse ack"))
+            .collect()
+    }
+
+    #[test]
+    fn test_handle() {
+        let engine = Mutex::new(Engine::default());
+
+        let acks = acks(
+            &engine,
+            "deposit,1,1,10.0\n\
+             {\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, \"amount\": 50.0}\n\
+             \n\
+             type,client,tx,amount,currency\n\
+             deposit,1,3,5.0,EUR\n\
+             depositt,1,4,1.0,EUR\n",
+        );
+
+        assert_eq!(acks.len(), 5);
+        assert_eq!(acks[0]["status"], "applied");
+        assert_eq!(acks[1]["status"], "rejected");
+        assert_eq!(acks[1]["tx"], 2);
+        assert_eq!(acks[2]["status"], "header");
+        assert_eq!(acks[3]["status"], "applied");
+        assert_eq!(acks[4]["status"], "error");
+        assert_eq!(acks[4]["line"], 6);
+
+        let engine = engine.lock().expect("Failed to lock engine");
+        assert_eq!(engine.ledger.client(1, None).total, 10.0);
+        assert_eq!(engine.ledger.client(1, "EUR".parse().ok()).total, 5.0);
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut output = vec![];
+        let (engine, tally) = replay(
+            Config::default(),
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,1,1,10.0\n\
+             withdrawal,1,2,50.0\n\
+             deposit,1,1,20.0\n"
+                .as_bytes(),
+            &mut output,
+        )
+        .expect("Failed to replay input");
+
+        assert_eq!(
+            tally,
+            Tally {
+                applied: 2,
+                rejected: 1,
+                ignored: 1,
+                errors: 0
+            }
+        );
+        assert_eq!(String::from_utf8_lossy(&output).lines().count(), 5);
+        assert_eq!(engine.ledger.client(1, None).total, 10.0);
+    }
+
+    #[test]
+    fn test_run() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+        let addr = listener.local_addr().expect("Failed to get address");
+        let engine = Arc::new(Mutex::new(Engine::default()));
+        let shared = Arc::clone(&engine);
+        thread::spawn(move || {
+            run(listener, shared).expect("Failed to run listener");
+        });
+
+        // every connection deposits to its own client
+        let connections: Vec<_> = (1..=4u16)
+            .map(|client| {
+                thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).expect("Failed to connect");
+                    let mut reader =
+                        BufReader::new(stream.try_clone().expect("Failed to clone stream"));
+                    for tx in 0..10u32 {
+                        let line =
+                            format!("deposit,{},{},1.0\n", client, u32::from(client) * 100 + tx);
+                        stream
+                            .write_all(line.as_bytes())
+                            .expect("Failed to send line");
+                        let mut ack = String::new();
+                        reader.read_line(&mut ack).expect("Failed to read ack");
+                        assert!(ack.contains("\"applied\""));
+                    }
+                })
+            })
+            .collect();
+        for connection in connections {
+            connection.join().expect("Failed to join connection");
+        }
+
+        let engine = engine.lock().expect("Failed to lock engine");
+        for client in 1..=4 {
+            assert_eq!(engine.ledger.client(client, None).total, 10.0);
+        }
+    }
+}
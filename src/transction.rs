@@ -1,173 +1,1047 @@
-use crate::client::{Client, ClientMap};
-use crate::csv_reader::{Record, RecordMap, Status, Type};
+use std::fmt;
 
-// process a single record
-pub fn process(record: &Record, clients: &ClientMap, records: &RecordMap) -> Client {
+use crate::client::{Client, ClientKey};
+use crate::config::{Config, WithdrawalDispute};
+use crate::csv_reader::{Record, RecordMap, Type};
+use crate::currency::Currency;
+use crate::fee::{FeeSchedule, HOUSE};
+use crate::ledger::{Account, Dispute, Hold, Key, Ledger, Pending};
+use crate::limits::{Movement, Rejection};
+use crate::risk::{Action, Flag};
+
+// Result of applying a single record, amount is None if the record was ignored
+#[derive(Debug, Clone, Copy)]
+pub struct Applied {
+    pub before: Client,
+    pub after: Client,
+    pub amount: Option<f64>,
+    pub fee: f64,
+}
+
+// Identifies a submitted record, by its idempotency key or otherwise by the tx it makes.
+// Disputes, resolves and chargebacks share the tx they refer to, so only their key identifies them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Submission {
+    Key(String),
+    Tx(u32),
+}
+
+impl fmt::Display for Submission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Submission::Key(key) => write!(f, "idempotency key {}", key),
+            Submission::Tx(tx) => write!(f, "tx {}", tx),
+        }
+    }
+}
+
+impl Submission {
+    pub fn of(record: &Record) -> Option<Submission> {
+        match (&record.idempotency_key, record.r#type) {
+            (Some(key), _) => Some(Submission::Key(key.clone())),
+            (None, Type::DEPOSIT | Type::WITHDRAWAL | Type::CONVERT | Type::TRANSFER) => {
+                Some(Submission::Tx(record.tx))
+            }
+            _ => None,
+        }
+    }
+}
+
+// How a record repeats the tx or idempotency key of an earlier record
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resubmission {
+    // the same record again, it is not applied again
+    Replay(Submission),
+    // other contents, the record is not applied
+    Conflict(Submission),
+}
+
+// A record with the tx or idempotency key of an earlier record with other contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub row: usize,
+    pub client: u16,
+    pub submission: Submission,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Error: {} was submitted before with other contents",
+            self.submission
+        )
+    }
+}
+
+// returns how the record repeats an earlier record with the same tx or idempotency key, if it does
+pub fn resubmission(record: &Record, ledger: &Ledger) -> Option<Resubmission> {
+    let submission = Submission::of(record)?;
+    let first = ledger.submitted.get(&submission)?;
+
+    // the row is where the record was submitted, not part of its contents
+    let first = Record {
+        row: record.row,
+        ..first.clone()
+    };
+    match first == *record {
+        true => Some(Resubmission::Replay(submission)),
+        false => Some(Resubmission::Conflict(submission)),
+    }
+}
+
+// applies the resolves of expired disputes and the clears of pending deposits followed by the record,
+// returns every applied record. A record with the tx or idempotency key of an earlier one is not applied.
+pub fn step(
+    record: &Record,
+    ledger: &mut Ledger,
+    records: &RecordMap,
+    config: &Config,
+) -> Vec<(Record, Applied)> {
+    let mut applied = vec![];
+
+    // a record submitted before is not applied again, one with other contents is a conflict
+    match resubmission(record, ledger) {
+        Some(Resubmission::Replay(_submission)) => return applied,
+        Some(Resubmission::Conflict(submission)) => {
+            ledger.conflicts.push(Conflict {
+                row: record.row,
+                client: record.client,
+                submission,
+            });
+            return applied;
+        }
+        None => {}
+    }
+    if let Some(submission) = Submission::of(record) {
+        ledger.submitted.insert(submission, record.clone());
+    }
+
+    for resolve in expire(record, ledger, config) {
+        let result = apply(&resolve, ledger, records, config);
+        applied.push((resolve, result));
+    }
+
+    for clear in clearing(record, ledger, config) {
+        let result = apply(&clear, ledger, records, config);
+        applied.push((clear, result));
+    }
+
+    // a convert or transfer also changes the balances of another client or currency
+    let counterparty =
+        counterparty(record, records).map(|(id, currency)| ledger.client(id, currency));
+
+    let result = apply(record, ledger, records, config);
+    applied.push((record.clone(), result));
+
+    if let (Some(counterparty), Some(amount)) = (counterparty, result.amount) {
+        // a convert of a client without an id is credited to the id it was given
+        let before = match record.r#type {
+            Type::CONVERT => Client {
+                client: result.after.client,
+                ..counterparty
+            },
+            _ => counterparty,
+        };
+        let after = ledger.client(before.client, before.currency);
+        let amount = match record.r#type {
+            Type::CONVERT => Some(after.available - before.available),
+            _ => Some(amount),
+        };
+
+        let leg = Record {
+            client: before.client,
+            currency: before.currency,
+            to: None,
+            recipient: None,
+            amount,
+            ..record.clone()
+        };
+        applied.push((
+            leg,
+            Applied {
+                before,
+                after,
+                amount,
+                fee: 0.0,
+            },
+        ));
+    }
+
+    applied
+}
+
+// returns the result of the record itself among the records applied by its step,
+// the expired resolves and clears come before the record, the leg of another client after it
+pub fn own(record: &Record, applied: Vec<(Record, Applied)>) -> Option<(Record, Applied)> {
+    applied.into_iter().rev().find(|(applied, _result)| {
+        applied.r#type == record.r#type
+            && applied.tx == record.tx
+            && applied.client == record.client
+            && applied.currency == record.currency
+    })
+}
+
+// process a single record and charge its fee, returns the client before and after
+pub fn apply(
+    record: &Record,
+    ledger: &mut Ledger,
+    records: &RecordMap,
+    config: &Config,
+) -> Applied {
+    let before = Client::find(record, currency(record, records), &ledger.clients);
+    // postings are made for the row of the record
+    ledger.row = record.row;
+    let (client, amount) = process(record, ledger, records, config);
+
+    // charge fees of applied transactions to the house account
+    let fee = match amount {
+        Some(amount) => charge(record, &client, amount, ledger, &config.fees),
+        None => 0.0,
+    };
+
+    Applied {
+        before,
+        after: ledger.client(client.client, client.currency),
+        amount,
+        fee,
+    }
+}
+
+// process a single record by posting it to the ledger,
+// returns the client and the amount that was applied
+pub fn process(
+    record: &Record,
+    ledger: &mut Ledger,
+    records: &RecordMap,
+    config: &Config,
+) -> (Client, Option<f64>) {
     // find client
-    let client = Client::find(&record, &clients);
+    let client = Client::find(record, currency(record, records), &ledger.clients);
+    ledger.open(&client);
+
+    // score the record, records that are not allowed as is are reported
+    let (score, rules, action) = config.risk.score(record, ledger, records);
+    if action != Action::Allow {
+        let rules: Vec<String> = rules.iter().map(|rule| rule.to_string()).collect();
+        ledger.flags.push(Flag {
+            row: record.row,
+            client: client.client,
+            tx: record.tx,
+            r#type: record.r#type,
+            amount: record.amount,
+            score,
+            action,
+            rules: rules.join(";"),
+        });
+    }
+    if action == Action::Reject {
+        return (client, None);
+    }
+    let held = action == Action::Hold;
+
+    // deposits and withdrawals must be within the limits of the client
+    if let Type::DEPOSIT | Type::WITHDRAWAL = record.r#type {
+        let fee = config
+            .fees
+            .charge(record.r#type, record.amount.unwrap_or(0.0));
+        let activity = ledger.activity.get(&(client.client, client.currency));
+        if let Err(reason) = config.limits.check(record, &client, fee, activity) {
+            ledger.rejections.push(Rejection {
+                row: record.row,
+                client: client.client,
+                tx: record.tx,
+                r#type: record.r#type,
+                amount: record.amount.unwrap_or(0.0),
+                reason,
+            });
+            return (client, None);
+        }
+    }
 
     // process record
-    let result = match &record.r#type {
-        Type::DEPOSIT => deposit(record, client),
-        Type::WITHDRAWAL => withdrawal(record, client),
-        Type::DISPUTE => dispute(record, client, records),
-        Type::RESOLVE => resolve(record, client, records),
-        Type::CHARGEBACK => chargeback(record, client, records),
+    let amount = match &record.r#type {
+        Type::DEPOSIT => deposit(record, &client, ledger, config, held),
+        Type::WITHDRAWAL => withdrawal(record, &client, ledger, config, held),
+        Type::DISPUTE => dispute(record, &client, ledger, records, config),
+        Type::RESOLVE => resolve(record, &client, ledger, records, config),
+        Type::CHARGEBACK => chargeback(record, &client, ledger, records, config),
+        Type::CONVERT => convert(record, &client, ledger, config),
+        Type::TRANSFER => transfer(record, &client, ledger, config),
+        Type::CLEAR => clear(record, &client, ledger),
+        Type::RELEASE => release(record, &client, ledger),
+        Type::REJECT => reject(record, &client, ledger),
     };
 
+    // keep the deposits and withdrawals with a timestamp for the limits over time
+    if let (Some(amount), Some(timestamp), Type::DEPOSIT | Type::WITHDRAWAL) =
+        (amount, record.timestamp, record.r#type)
+    {
+        ledger
+            .activity
+            .entry((client.client, client.currency))
+            .or_default()
+            .push(Movement {
+                timestamp,
+                r#type: record.r#type,
+                amount,
+            });
+    }
+
+    // keep the lifecycle of disputed transactions
+    if let (Some(amount), Type::DISPUTE | Type::RESOLVE | Type::CHARGEBACK) =
+        (amount, record.r#type)
+    {
+        let open = ledger.disputes.contains_key(&record.tx);
+        ledger
+            .lifecycles
+            .entry(record.tx)
+            .or_default()
+            .push(record, amount, open);
+    }
+
     // return result
-    result
+    (ledger.client(client.client, client.currency), amount)
 }
 
-fn deposit(record: &Record, mut client: Client) -> Client {
-    // do nothing if we do not have a valid amount
-    let amount = match record.amount {
-        Some(amount) => amount,
-        None => return client,
+// returns the client and currency a record changes besides its own, if any
+pub fn counterparty(record: &Record, records: &RecordMap) -> Option<ClientKey> {
+    match record.r#type {
+        Type::CONVERT => Some((record.client, record.to)),
+        Type::TRANSFER => record
+            .recipient
+            .map(|recipient| (recipient, record.currency)),
+        // disputes of a transfer move the funds of its recipient
+        Type::DISPUTE | Type::RESOLVE | Type::CHARGEBACK => {
+            let tx = records.get(&record.tx)?;
+            match tx.r#type {
+                Type::TRANSFER => tx
+                    .recipient
+                    .map(|recipient| (recipient, currency(record, records))),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// returns the currency of a record, disputes, resolves and chargebacks without one
+// are in the currency of the original tx
+pub fn currency(record: &Record, records: &RecordMap) -> Option<Currency> {
+    match (record.r#type, record.currency) {
+        (Type::DEPOSIT | Type::WITHDRAWAL | Type::CONVERT | Type::TRANSFER, currency)
+        | (_, currency @ Some(_)) => currency,
+        _ => records.get(&record.tx).and_then(|tx| tx.currency),
+    }
+}
+
+// charges the fee of the applied amount by posting it to the house account, returns the fee
+pub fn charge(
+    record: &Record,
+    client: &Client,
+    amount: f64,
+    ledger: &mut Ledger,
+    schedule: &FeeSchedule,
+) -> f64 {
+    let fee = schedule.charge(record.r#type, amount);
+    if fee == 0.0 {
+        return 0.0;
+    }
+
+    // move the fee from the client to the house account, the fee of a deposit that has not cleared
+    // is taken from pending and the fee of a deposit on hold from the funds on hold
+    match (record.r#type, ledger.holds.get_mut(&record.tx)) {
+        (Type::DEPOSIT, Some(hold)) => {
+            hold.amount -= fee;
+            ledger.post(
+                record.tx,
+                client.currency,
+                (client.client, Account::OnHold),
+                (HOUSE, Account::Fees),
+                fee,
+            );
+        }
+        (Type::DEPOSIT, None) => {
+            take(record.tx, client, ledger, (HOUSE, Account::Fees), fee);
+        }
+        _ => ledger.post(
+            record.tx,
+            client.currency,
+            (client.client, Account::Available),
+            (HOUSE, Account::Fees),
+            fee,
+        ),
+    }
+
+    fee
+}
+
+// moves the amount from the funds of the client, the pending part of the tx is taken first
+// and the rest from available, returns the part taken from pending
+fn take(tx: u32, client: &Client, ledger: &mut Ledger, to: Key, amount: f64) -> f64 {
+    let pending = ledger.take_pending(tx, amount);
+    if pending > 0.0 {
+        ledger.post(
+            tx,
+            client.currency,
+            (client.client, Account::Pending),
+            to,
+            pending,
+        );
+    }
+    if amount > pending {
+        ledger.post(
+            tx,
+            client.currency,
+            (client.client, Account::Available),
+            to,
+            amount - pending,
+        );
+    }
+
+    pending
+}
+
+// returns resolves for the disputes left open longer than the expiry at the time of the record,
+// they are to be applied before the record
+pub fn expire(record: &Record, ledger: &Ledger, config: &Config) -> Vec<Record> {
+    let (expiry, now) = match (config.dispute_expiry, record.timestamp) {
+        (Some(expiry), Some(now)) => (expiry, now),
+        _ => return vec![],
+    };
+
+    let mut resolves: Vec<Record> = ledger
+        .disputes
+        .iter()
+        .filter(|(_tx, dispute)| dispute.opened.is_some_and(|opened| now - opened > expiry))
+        .map(|(tx, dispute)| Record {
+            r#type: Type::RESOLVE,
+            client: dispute.client,
+            tx: *tx,
+            amount: None,
+            status: None,
+            timestamp: Some(now),
+            currency: dispute.currency,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            // expired by this record
+            row: record.row,
+        })
+        .collect();
+    resolves.sort_by_key(|resolve| resolve.tx);
+
+    resolves
+}
+
+// returns clears for the pending deposits that cleared by the time of the record,
+// they are to be applied before the record. The deposits of a locked client do not clear.
+pub fn clearing(record: &Record, ledger: &Ledger, config: &Config) -> Vec<Record> {
+    let cleared = |pending: &Pending| {
+        let rows = config
+            .clearing_rows
+            .is_some_and(|rows| record.row.saturating_sub(pending.row) > rows);
+        let period = match (config.clearing_period, record.timestamp, pending.timestamp) {
+            (Some(period), Some(now), Some(deposited)) => now - deposited >= period,
+            _ => false,
+        };
+
+        rows || period
     };
 
-    // increase available and total
-    client.available = client.available + amount;
-    client.total = client.total + amount;
+    let mut clears: Vec<Record> = ledger
+        .pending
+        .iter()
+        .filter(|(_tx, pending)| pending.amount > 0.0 && cleared(pending))
+        .filter(|(_tx, pending)| !ledger.client(pending.client, pending.currency).locked)
+        .map(|(tx, pending)| Record {
+            r#type: Type::CLEAR,
+            client: pending.client,
+            tx: *tx,
+            amount: None,
+            status: None,
+            timestamp: record.timestamp,
+            currency: pending.currency,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            // cleared by this record
+            row: record.row,
+        })
+        .collect();
+    clears.sort_by_key(|clear| clear.tx);
 
-    client
+    clears
 }
 
-fn withdrawal(record: &Record, mut client: Client) -> Client {
+fn deposit(
+    record: &Record,
+    client: &Client,
+    ledger: &mut Ledger,
+    config: &Config,
+    held: bool,
+) -> Option<f64> {
     // do nothing if we do not have a valid amount
-    let amount = match record.amount {
-        Some(amount) => amount,
-        None => return client,
+    let amount = record.amount?;
+
+    // move funds into available, into the on hold account until it is reviewed,
+    // or into pending until it clears
+    let to = match (held, config.clearing()) {
+        (true, _) => Account::OnHold,
+        (false, true) => Account::Pending,
+        (false, false) => Account::Available,
     };
+    if held {
+        hold(record, client, ledger, amount);
+    }
+    if to == Account::Pending {
+        ledger.pending.insert(
+            record.tx,
+            Pending {
+                client: client.client,
+                currency: client.currency,
+                row: record.row,
+                timestamp: record.timestamp,
+                amount,
+            },
+        );
+    }
+    ledger.post(
+        record.tx,
+        client.currency,
+        (client.client, Account::External),
+        (client.client, to),
+        amount,
+    );
+
+    Some(amount)
+}
+
+// keeps a deposit or withdrawal held by the risk rules until it is reviewed
+fn hold(record: &Record, client: &Client, ledger: &mut Ledger, amount: f64) {
+    ledger.holds.insert(
+        record.tx,
+        Hold {
+            client: client.client,
+            currency: client.currency,
+            r#type: record.r#type,
+            amount,
+            rejected: false,
+        },
+    );
+}
 
-    // can only withdrawal if the client has enough available
-    if client.available > amount {
-        client.available = client.available - amount;
-        client.total = client.total - amount;
+// applies a deposit or withdrawal on hold, the deposit is made available and the withdrawal paid out
+fn release(record: &Record, client: &Client, ledger: &mut Ledger) -> Option<f64> {
+    // only the client of a record that is still on hold may release it
+    let hold = *ledger.holds.get(&record.tx)?;
+    if hold.rejected || hold.client != client.client || hold.currency != client.currency {
+        return None;
     }
+    ledger.holds.remove(&record.tx);
+
+    let to = match hold.r#type {
+        Type::DEPOSIT => Account::Available,
+        _ => Account::External,
+    };
+    ledger.post(
+        record.tx,
+        client.currency,
+        (client.client, Account::OnHold),
+        (client.client, to),
+        hold.amount,
+    );
 
-    client
+    Some(hold.amount)
 }
 
-fn dispute(record: &Record, mut client: Client, records: &RecordMap) -> Client {
-    // find transaction or ignore
-    let tx = match records.get(&record.tx) {
-        Some(tx) => tx,
-        None => return client,
+// reverses a deposit or withdrawal on hold, the deposit is returned and the withdrawal cancelled
+fn reject(record: &Record, client: &Client, ledger: &mut Ledger) -> Option<f64> {
+    // only the client of a record that is still on hold may reject it
+    let hold = ledger.holds.get_mut(&record.tx)?;
+    if hold.rejected || hold.client != client.client || hold.currency != client.currency {
+        return None;
+    }
+    hold.rejected = true;
+    let (r#type, amount) = (hold.r#type, hold.amount);
+
+    let to = match r#type {
+        Type::DEPOSIT => Account::External,
+        _ => Account::Available,
     };
+    ledger.post(
+        record.tx,
+        client.currency,
+        (client.client, Account::OnHold),
+        (client.client, to),
+        amount,
+    );
+
+    Some(amount)
+}
 
+// makes the rest of a pending deposit available
+fn clear(record: &Record, client: &Client, ledger: &mut Ledger) -> Option<f64> {
+    let amount = ledger.pending.remove(&record.tx)?.amount;
+    if amount <= 0.0 {
+        return None;
+    }
+
+    ledger.post(
+        record.tx,
+        client.currency,
+        (client.client, Account::Pending),
+        (client.client, Account::Available),
+        amount,
+    );
+
+    Some(amount)
+}
+
+// Returns true if the client has more available than the amount and its fee,
+// a balance of exactly the amount and fee is not enough
+fn funded(client: &Client, r#type: Type, amount: f64, config: &Config) -> bool {
+    client.available > amount + config.fees.charge(r#type, amount)
+}
+
+fn withdrawal(
+    record: &Record,
+    client: &Client,
+    ledger: &mut Ledger,
+    config: &Config,
+    held: bool,
+) -> Option<f64> {
     // do nothing if we do not have a valid amount
-    let amount = match tx.amount {
-        Some(amount) => amount,
-        None => return client,
-    };
+    let amount = record.amount?;
 
-    // decrease available
-    if tx.r#type == Type::DEPOSIT {
-        client.available = client.available - amount;
+    // can only withdrawal if the client has enough available for the amount and its fee
+    if !funded(client, Type::WITHDRAWAL, amount, config) {
+        return None;
     }
 
-    // increase held
-    client.held = client.held + amount;
+    // a withdrawal on hold is not paid out, the funds stay in the on hold account until it is reviewed
+    let to = match held {
+        true => (client.client, Account::OnHold),
+        false => (client.client, Account::External),
+    };
+    if held {
+        hold(record, client, ledger, amount);
+    }
+    ledger.post(
+        record.tx,
+        client.currency,
+        (client.client, Account::Available),
+        to,
+        amount,
+    );
 
-    client
+    Some(amount)
 }
 
-fn resolve(record: &Record, mut client: Client, records: &RecordMap) -> Client {
-    // find transaction or ignore
-    let tx = match records.get(&record.tx) {
-        Some(tx) => tx,
-        None => return client,
+fn convert(record: &Record, client: &Client, ledger: &mut Ledger, config: &Config) -> Option<f64> {
+    // do nothing if we do not have a valid amount
+    let amount = record.amount?;
+
+    // both currencies are required and must differ
+    let (from, to) = match (client.currency, record.to) {
+        (Some(from), Some(to)) if from != to => (from, to),
+        _ => return None,
     };
 
-    // verify if the tx is under dispute
-    if tx.status.is_none() {
-        return client;
-    }
-    let status = tx.status.expect("Failed to get transaction status");
-    if status != Status::DISPUTED {
-        return client;
+    // can only convert if the client has enough available for the amount and its fee
+    if !funded(client, Type::CONVERT, amount, config) {
+        return None;
     }
 
+    // ignore conversions without a rate at the time of the record
+    let date = record.timestamp.map(|timestamp| timestamp.date_naive());
+    let converted = config.rates.convert(amount, from, to, date)?;
+
+    // pay into the exchange in one currency and out of it in the other
+    ledger.post(
+        record.tx,
+        Some(from),
+        (client.client, Account::Available),
+        (HOUSE, Account::Exchange),
+        amount,
+    );
+    ledger.post(
+        record.tx,
+        Some(to),
+        (HOUSE, Account::Exchange),
+        (client.client, Account::Available),
+        converted,
+    );
+
+    Some(amount)
+}
+
+fn transfer(record: &Record, client: &Client, ledger: &mut Ledger, config: &Config) -> Option<f64> {
     // do nothing if we do not have a valid amount
-    let amount = match tx.amount {
-        Some(amount) => amount,
-        None => return client,
+    let amount = record.amount?;
+
+    // the recipient must be another client with an id
+    let recipient = match record.recipient {
+        Some(recipient) if recipient != 0 && recipient != client.client => recipient,
+        _ => return None,
     };
 
-    // decrease held
-    client.held = client.held - amount;
+    // neither client may be locked
+    if client.locked || ledger.client(recipient, client.currency).locked {
+        return None;
+    }
 
-    // increase available
-    if tx.r#type == Type::DEPOSIT {
-        client.available = client.available + amount;
+    // can only transfer if the client has enough available for the amount and its fee
+    if !funded(client, Type::TRANSFER, amount, config) {
+        return None;
     }
 
-    client
+    // move funds from one client to the other in one posting
+    ledger.post(
+        record.tx,
+        client.currency,
+        (client.client, Account::Available),
+        (recipient, Account::Available),
+        amount,
+    );
+
+    Some(amount)
 }
 
-fn chargeback(record: &Record, mut client: Client, records: &RecordMap) -> Client {
+fn dispute(
+    record: &Record,
+    client: &Client,
+    ledger: &mut Ledger,
+    records: &RecordMap,
+    config: &Config,
+) -> Option<f64> {
     // find transaction or ignore
-    let tx = match records.get(&record.tx) {
-        Some(tx) => tx,
-        None => return client,
+    let tx = records.get(&record.tx)?;
+
+    // only the client of the transaction can dispute it
+    if tx.client != record.client {
+        return None;
+    }
+
+    // ignore records in another currency than the transaction
+    if client.currency != tx.currency {
+        return None;
+    }
+
+    // do nothing if we do not have a valid amount
+    let original = tx.amount?;
+
+    // records on hold can not be disputed until they are released, rejected ones never
+    if ledger.holds.contains_key(&record.tx) {
+        return None;
+    }
+
+    // ignore disputes later than the window allows
+    if let (Some(window), Some(opened), Some(timestamp)) =
+        (config.dispute_window, record.timestamp, tx.timestamp)
+    {
+        if opened - timestamp > window {
+            return None;
+        }
+    }
+
+    // ignore disputes on transfers that never had a recipient
+    if tx.r#type == Type::TRANSFER && tx.recipient.is_none() {
+        return None;
+    }
+
+    // ignore disputes on withdrawals if the policy does not allow them
+    if tx.r#type == Type::WITHDRAWAL && config.withdrawal_dispute == WithdrawalDispute::Disallow {
+        return None;
+    }
+
+    // a resolved tx may only be disputed again if the rules allow it
+    if !ledger.disputes.contains_key(&record.tx) {
+        let resolved = ledger
+            .lifecycles
+            .get(&record.tx)
+            .map_or(0, |lifecycle| lifecycle.resolved());
+        if !config.redispute.allows(resolved) {
+            return None;
+        }
+    }
+
+    // dispute the rest of the transaction unless a smaller amount is provided
+    let disputable = ledger.disputable(record.tx, original);
+    let amount = match record.amount {
+        Some(amount) if amount > 0.0 && amount <= disputable => amount,
+        Some(_) => return None,
+        None => disputable,
     };
+    if amount <= 0.0 {
+        return None;
+    }
+
+    // disputes on withdrawals follow the configured policy, transfers are held at the recipient
+    let mut pending = 0.0;
+    if tx.r#type == Type::WITHDRAWAL {
+        withdrawal_dispute(record, client, ledger, amount, config);
+    } else if tx.r#type == Type::TRANSFER {
+        transfer_dispute(record, tx, ledger, amount);
+    } else {
+        // hold the disputed funds from pending if the deposit has not cleared, or from available
+        pending = take(
+            record.tx,
+            client,
+            ledger,
+            (client.client, Account::Held),
+            amount,
+        );
+    }
+
+    // open the dispute or add to the one that is open
+    let dispute = ledger.disputes.entry(record.tx).or_insert(Dispute {
+        client: client.client,
+        currency: client.currency,
+        opened: record.timestamp,
+        amount: 0.0,
+        pending: 0.0,
+    });
+    dispute.amount += amount;
+    dispute.pending += pending;
+
+    Some(amount)
+}
+
+fn resolve(
+    record: &Record,
+    client: &Client,
+    ledger: &mut Ledger,
+    records: &RecordMap,
+    config: &Config,
+) -> Option<f64> {
+    // find transaction or ignore
+    let tx = records.get(&record.tx)?;
+
+    // only the client of the transaction can dispute it
+    if tx.client != record.client {
+        return None;
+    }
+
+    // ignore records in another currency than the transaction
+    if client.currency != tx.currency {
+        return None;
+    }
 
     // verify if the tx is under dispute
-    if tx.status.is_none() {
-        return client;
+    let dispute = ledger.disputes.remove(&record.tx)?;
+    let amount = dispute.amount;
+
+    // disputes on withdrawals follow the configured policy
+    if tx.r#type == Type::WITHDRAWAL {
+        withdrawal_dispute(record, client, ledger, amount, config);
+        return Some(amount);
+    }
+
+    // transfers are held at the recipient
+    if tx.r#type == Type::TRANSFER {
+        transfer_dispute(record, tx, ledger, amount);
+        return Some(amount);
     }
-    let status = tx.status.expect("Failed to get transaction status");
-    if status != Status::DISPUTED {
-        return client;
+
+    // funds held from a deposit that has not cleared yet return to pending
+    let pending = match ledger.pending.get_mut(&record.tx) {
+        Some(deposit) => {
+            let pending = dispute.pending.min(amount);
+            deposit.amount += pending;
+            pending
+        }
+        None => 0.0,
+    };
+    if pending > 0.0 {
+        ledger.post(
+            record.tx,
+            client.currency,
+            (client.client, Account::Held),
+            (client.client, Account::Pending),
+            pending,
+        );
     }
 
-    // do nothing if we do not have a valid amount
-    let amount = match tx.amount {
-        Some(amount) => amount,
-        None => return client,
+    // release held, the original transaction stands
+    if amount > pending {
+        ledger.post(
+            record.tx,
+            client.currency,
+            (client.client, Account::Held),
+            (client.client, Account::Available),
+            amount - pending,
+        );
+    }
+
+    Some(amount)
+}
+
+fn chargeback(
+    record: &Record,
+    client: &Client,
+    ledger: &mut Ledger,
+    records: &RecordMap,
+    config: &Config,
+) -> Option<f64> {
+    // find transaction or ignore
+    let tx = records.get(&record.tx)?;
+
+    // only the client of the transaction can dispute it
+    if tx.client != record.client {
+        return None;
+    }
+
+    // ignore records in another currency than the transaction
+    if client.currency != tx.currency {
+        return None;
+    }
+
+    // verify if the tx is under dispute
+    let disputed = match ledger.disputes.get(&record.tx) {
+        Some(dispute) => dispute.amount,
+        None => return None,
     };
 
-    // decrease held
-    client.held = client.held - amount;
+    // charge back the whole dispute unless a smaller amount is provided
+    let amount = match record.amount {
+        Some(amount) if amount > 0.0 && amount <= disputed => amount,
+        Some(_) => return None,
+        None => disputed,
+    };
 
-    // decrease total
-    if tx.r#type == Type::DEPOSIT {
-        client.total = client.total - amount;
+    // the rest of a partial chargeback stays under dispute
+    if amount < disputed {
+        if let Some(dispute) = ledger.disputes.get_mut(&record.tx) {
+            dispute.amount -= amount;
+        }
+    } else {
+        ledger.disputes.remove(&record.tx);
     }
+    *ledger.charged_back.entry(record.tx).or_insert(0.0) += amount;
 
-    // increase available and total
+    // disputes on withdrawals follow the configured policy
     if tx.r#type == Type::WITHDRAWAL {
-        client.total = client.total + amount;
-        client.available = client.available + amount;
+        withdrawal_dispute(record, client, ledger, amount, config);
+        return Some(amount);
+    }
+
+    // transfers are held at the recipient
+    if tx.r#type == Type::TRANSFER {
+        transfer_dispute(record, tx, ledger, amount);
+        return Some(amount);
+    }
+
+    // release held, the original transaction is reversed
+    ledger.post(
+        record.tx,
+        client.currency,
+        (client.client, Account::Held),
+        (client.client, Account::External),
+        amount,
+    );
+
+    // freeze client
+    ledger.lock(client.client);
+
+    Some(amount)
+}
+
+// applies a dispute, resolve or chargeback of a withdrawal as described by the policy
+fn withdrawal_dispute(
+    record: &Record,
+    client: &Client,
+    ledger: &mut Ledger,
+    amount: f64,
+    config: &Config,
+) {
+    let policy = config.withdrawal_dispute;
+    for (from, to) in policy.postings(record.r#type) {
+        ledger.post(
+            record.tx,
+            client.currency,
+            (client.client, *from),
+            (client.client, *to),
+            amount,
+        );
     }
 
     // freeze client
-    client.locked = true;
+    if record.r#type == Type::CHARGEBACK {
+        ledger.lock(client.client);
+    }
+}
+
+// applies a dispute, resolve or chargeback of a transfer, the funds are held at the recipient
+// and a chargeback returns them to the sender and freezes the recipient
+fn transfer_dispute(record: &Record, tx: &Record, ledger: &mut Ledger, amount: f64) {
+    let recipient = match tx.recipient {
+        Some(recipient) => recipient,
+        None => return,
+    };
+
+    let (from, to) = match record.r#type {
+        Type::DISPUTE => ((recipient, Account::Available), (recipient, Account::Held)),
+        Type::RESOLVE => ((recipient, Account::Held), (recipient, Account::Available)),
+        Type::CHARGEBACK => ((recipient, Account::Held), (tx.client, Account::Available)),
+        _ => return,
+    };
+    ledger.post(record.tx, tx.currency, from, to, amount);
 
-    client
+    // freeze recipient
+    if record.r#type == Type::CHARGEBACK {
+        ledger.lock(recipient);
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+    use crate::config::Redispute;
     use crate::csv_reader::{Status, Type};
+    use crate::fee::Fee;
     use std::collections::HashMap;
 
+    fn schedule() -> FeeSchedule {
+        let mut schedule = FeeSchedule::default();
+        schedule.insert(Fee {
+            r#type: Type::DEPOSIT,
+            min: None,
+            flat: 0.0,
+            percent: 1.0,
+        });
+        schedule.insert(Fee {
+            r#type: Type::CHARGEBACK,
+            min: None,
+            flat: 15.0,
+            percent: 0.0,
+        });
+
+        schedule
+    }
+
     #[test]
     fn test_deposit() {
-        let clients: ClientMap = HashMap::new();
+        let mut ledger = Ledger::default();
+        let config = Config::default();
         let record_deposit = Record {
             r#type: Type::DEPOSIT,
             client: 1,
             tx: 1,
             amount: Some(10.0),
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let mut records_map = HashMap::new();
         records_map.insert(record_deposit.tx, record_deposit.clone());
 
-        let client = process(&record_deposit, &clients, &records_map);
+        let (client, _amount) = process(&record_deposit, &mut ledger, &records_map, &config);
 
         assert_eq!(client.available, 10.0);
         assert_eq!(client.total, 10.0);
@@ -176,13 +1050,20 @@ mod tests {
 
     #[test]
     fn test_withdrawal() {
-        let mut clients: ClientMap = HashMap::new();
+        let mut ledger = Ledger::default();
+        let config = Config::default();
         let record_deposit = Record {
             r#type: Type::DEPOSIT,
             client: 1,
             tx: 1,
             amount: Some(10.0),
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_withdrawal = Record {
             r#type: Type::WITHDRAWAL,
@@ -190,15 +1071,19 @@ mod tests {
             tx: 2,
             amount: Some(2.0),
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let mut records_map = HashMap::new();
         records_map.insert(record_deposit.tx, record_deposit.clone());
         records_map.insert(record_withdrawal.tx, record_withdrawal.clone());
 
-        let mut client = process(&record_deposit, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_withdrawal, &clients, &records_map);
-        clients.insert(client.client, client);
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        let (client, _amount) = process(&record_withdrawal, &mut ledger, &records_map, &config);
 
         assert_eq!(client.available, 8.0);
         assert_eq!(client.total, 8.0);
@@ -207,13 +1092,20 @@ mod tests {
 
     #[test]
     fn test_dispute_deposit() {
-        let mut clients: ClientMap = HashMap::new();
+        let mut ledger = Ledger::default();
+        let config = Config::default();
         let record_deposit = Record {
             r#type: Type::DEPOSIT,
             client: 1,
             tx: 1,
             amount: Some(10.0),
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_withdrawal = Record {
             r#type: Type::DEPOSIT,
@@ -221,6 +1113,12 @@ mod tests {
             tx: 2,
             amount: Some(2.0),
             status: Some(Status::DISPUTED),
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_dispute = Record {
             r#type: Type::DISPUTE,
@@ -228,17 +1126,20 @@ mod tests {
             tx: 2,
             amount: None,
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let mut records_map = HashMap::new();
         records_map.insert(record_deposit.tx, record_deposit.clone());
         records_map.insert(record_withdrawal.tx, record_withdrawal.clone());
 
-        let mut client = process(&record_deposit, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_withdrawal, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_dispute, &clients, &records_map);
-        clients.insert(client.client, client);
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        process(&record_withdrawal, &mut ledger, &records_map, &config);
+        let (client, _amount) = process(&record_dispute, &mut ledger, &records_map, &config);
 
         assert_eq!(client.available, 10.0);
         assert_eq!(client.held, 2.0);
@@ -248,13 +1149,20 @@ mod tests {
 
     #[test]
     fn test_dispute_withdrawal() {
-        let mut clients: ClientMap = HashMap::new();
+        let mut ledger = Ledger::default();
+        let config = Config::default();
         let record_deposit = Record {
             r#type: Type::DEPOSIT,
             client: 1,
             tx: 1,
             amount: Some(10.0),
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_withdrawal = Record {
             r#type: Type::WITHDRAWAL,
@@ -262,6 +1170,12 @@ mod tests {
             tx: 2,
             amount: Some(2.0),
             status: Some(Status::DISPUTED),
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_dispute = Record {
             r#type: Type::DISPUTE,
@@ -269,33 +1183,101 @@ mod tests {
             tx: 2,
             amount: None,
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let mut records_map = HashMap::new();
         records_map.insert(record_deposit.tx, record_deposit.clone());
         records_map.insert(record_withdrawal.tx, record_withdrawal.clone());
 
-        let mut client = process(&record_deposit, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_withdrawal, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_dispute, &clients, &records_map);
-        clients.insert(client.client, client);
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        process(&record_withdrawal, &mut ledger, &records_map, &config);
+        let (client, _amount) = process(&record_dispute, &mut ledger, &records_map, &config);
 
         assert_eq!(client.available, 8.0);
         assert_eq!(client.held, 2.0);
-        assert_eq!(client.total, 8.0);
+        assert_eq!(client.total, 10.0);
+        assert_eq!(client.locked, false);
+    }
+
+    #[test]
+    fn test_dispute_other_client() {
+        let mut ledger = Ledger::default();
+        let config = Config::default();
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_dispute = Record {
+            r#type: Type::DISPUTE,
+            client: 2,
+            tx: 1,
+            amount: None,
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_chargeback = Record {
+            r#type: Type::CHARGEBACK,
+            client: 2,
+            tx: 1,
+            amount: None,
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let mut records_map = HashMap::new();
+        records_map.insert(record_deposit.tx, record_deposit.clone());
+
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        let (_client, amount) = process(&record_dispute, &mut ledger, &records_map, &config);
+        process(&record_chargeback, &mut ledger, &records_map, &config);
+
+        assert_eq!(amount, None);
+        assert!(ledger.disputes.is_empty());
+        let client = ledger.client(1, None);
+        assert_eq!(client.available, 10.0);
+        assert_eq!(client.held, 0.0);
         assert_eq!(client.locked, false);
     }
 
     #[test]
     fn test_resolve_deposit() {
-        let mut clients: ClientMap = HashMap::new();
+        let mut ledger = Ledger::default();
+        let config = Config::default();
         let record_deposit = Record {
             r#type: Type::DEPOSIT,
             client: 1,
             tx: 1,
             amount: Some(10.0),
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_withdrawal = Record {
             r#type: Type::DEPOSIT,
@@ -303,6 +1285,12 @@ mod tests {
             tx: 2,
             amount: Some(2.0),
             status: Some(Status::DISPUTED),
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_dispute = Record {
             r#type: Type::DISPUTE,
@@ -310,6 +1298,12 @@ mod tests {
             tx: 2,
             amount: None,
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_resolve = Record {
             r#type: Type::RESOLVE,
@@ -317,20 +1311,22 @@ mod tests {
             tx: 2,
             amount: None,
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
 
         let mut records_map = HashMap::new();
         records_map.insert(record_deposit.tx, record_deposit.clone());
         records_map.insert(record_withdrawal.tx, record_withdrawal.clone());
 
-        let mut client = process(&record_deposit, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_withdrawal, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_dispute, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_resolve, &clients, &records_map);
-        clients.insert(client.client, client);
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        process(&record_withdrawal, &mut ledger, &records_map, &config);
+        process(&record_dispute, &mut ledger, &records_map, &config);
+        let (client, _amount) = process(&record_resolve, &mut ledger, &records_map, &config);
 
         assert_eq!(client.available, 12.0);
         assert_eq!(client.held, 0.0);
@@ -340,13 +1336,20 @@ mod tests {
 
     #[test]
     fn test_resolve_withdrawal() {
-        let mut clients: ClientMap = HashMap::new();
+        let mut ledger = Ledger::default();
+        let config = Config::default();
         let record_deposit = Record {
             r#type: Type::DEPOSIT,
             client: 1,
             tx: 1,
             amount: Some(10.0),
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_withdrawal = Record {
             r#type: Type::WITHDRAWAL,
@@ -354,6 +1357,12 @@ mod tests {
             tx: 2,
             amount: Some(2.0),
             status: Some(Status::DISPUTED),
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_dispute = Record {
             r#type: Type::DISPUTE,
@@ -361,6 +1370,12 @@ mod tests {
             tx: 2,
             amount: None,
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_resolve = Record {
             r#type: Type::RESOLVE,
@@ -368,20 +1383,22 @@ mod tests {
             tx: 2,
             amount: None,
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
 
         let mut records_map = HashMap::new();
         records_map.insert(record_deposit.tx, record_deposit.clone());
         records_map.insert(record_withdrawal.tx, record_withdrawal.clone());
 
-        let mut client = process(&record_deposit, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_withdrawal, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_dispute, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_resolve, &clients, &records_map);
-        clients.insert(client.client, client);
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        process(&record_withdrawal, &mut ledger, &records_map, &config);
+        process(&record_dispute, &mut ledger, &records_map, &config);
+        let (client, _amount) = process(&record_resolve, &mut ledger, &records_map, &config);
 
         assert_eq!(client.available, 8.0);
         assert_eq!(client.held, 0.0);
@@ -391,13 +1408,20 @@ mod tests {
 
     #[test]
     fn test_chargeback_deposit() {
-        let mut clients: ClientMap = HashMap::new();
+        let mut ledger = Ledger::default();
+        let config = Config::default();
         let record_deposit = Record {
             r#type: Type::DEPOSIT,
             client: 1,
             tx: 1,
             amount: Some(10.0),
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_withdrawal = Record {
             r#type: Type::DEPOSIT,
@@ -405,6 +1429,12 @@ mod tests {
             tx: 2,
             amount: Some(2.0),
             status: Some(Status::DISPUTED),
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_dispute = Record {
             r#type: Type::DISPUTE,
@@ -412,6 +1442,12 @@ mod tests {
             tx: 2,
             amount: None,
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_resolve = Record {
             r#type: Type::CHARGEBACK,
@@ -419,20 +1455,22 @@ mod tests {
             tx: 2,
             amount: None,
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
 
         let mut records_map = HashMap::new();
         records_map.insert(record_deposit.tx, record_deposit.clone());
         records_map.insert(record_withdrawal.tx, record_withdrawal.clone());
 
-        let mut client = process(&record_deposit, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_withdrawal, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_dispute, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_resolve, &clients, &records_map);
-        clients.insert(client.client, client);
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        process(&record_withdrawal, &mut ledger, &records_map, &config);
+        process(&record_dispute, &mut ledger, &records_map, &config);
+        let (client, _amount) = process(&record_resolve, &mut ledger, &records_map, &config);
 
         assert_eq!(client.available, 10.0);
         assert_eq!(client.held, 0.0);
@@ -442,13 +1480,20 @@ mod tests {
 
     #[test]
     fn test_chargeback_withdrawal() {
-        let mut clients: ClientMap = HashMap::new();
+        let mut ledger = Ledger::default();
+        let config = Config::default();
         let record_deposit = Record {
             r#type: Type::DEPOSIT,
             client: 1,
             tx: 1,
             amount: Some(10.0),
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_withdrawal = Record {
             r#type: Type::WITHDRAWAL,
@@ -456,6 +1501,12 @@ mod tests {
             tx: 2,
             amount: Some(2.0),
             status: Some(Status::DISPUTED),
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_dispute = Record {
             r#type: Type::DISPUTE,
@@ -463,6 +1514,12 @@ mod tests {
             tx: 2,
             amount: None,
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
         let record_resolve = Record {
             r#type: Type::CHARGEBACK,
@@ -470,24 +1527,1455 @@ mod tests {
             tx: 2,
             amount: None,
             status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
         };
 
         let mut records_map = HashMap::new();
         records_map.insert(record_deposit.tx, record_deposit.clone());
         records_map.insert(record_withdrawal.tx, record_withdrawal.clone());
 
-        let mut client = process(&record_deposit, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_withdrawal, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_dispute, &clients, &records_map);
-        clients.insert(client.client, client);
-        client = process(&record_resolve, &clients, &records_map);
-        clients.insert(client.client, client);
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        process(&record_withdrawal, &mut ledger, &records_map, &config);
+        process(&record_dispute, &mut ledger, &records_map, &config);
+        let (client, _amount) = process(&record_resolve, &mut ledger, &records_map, &config);
 
         assert_eq!(client.available, 10.0);
         assert_eq!(client.held, 0.0);
         assert_eq!(client.total, 10.0);
         assert_eq!(client.locked, true);
     }
+
+    #[test]
+    fn test_charge_deposit() {
+        let mut ledger = Ledger::default();
+        let config = Config {
+            fees: schedule(),
+            ..Default::default()
+        };
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let records_map = HashMap::new();
+
+        let Applied { fee, .. } = apply(&record_deposit, &mut ledger, &records_map, &config);
+
+        let client = ledger.client(1, None);
+        assert_eq!(fee, 0.1);
+        assert_eq!(client.available, 9.9);
+        assert_eq!(client.total, 9.9);
+        assert_eq!(ledger.fees(None), 0.1);
+    }
+
+    #[test]
+    fn test_charge_ignored() {
+        let mut ledger = Ledger::default();
+        let config = Config {
+            fees: schedule(),
+            ..Default::default()
+        };
+        let record_withdrawal = Record {
+            r#type: Type::WITHDRAWAL,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let records_map = HashMap::new();
+
+        let Applied { fee, .. } = apply(&record_withdrawal, &mut ledger, &records_map, &config);
+
+        assert_eq!(fee, 0.0);
+        assert_eq!(ledger.client(1, None).total, 0.0);
+        assert_eq!(ledger.fees(None), 0.0);
+    }
+
+    #[test]
+    fn test_charge_funds() {
+        let mut ledger = Ledger::default();
+        let mut config = Config::default();
+        config.fees.insert(Fee {
+            r#type: Type::WITHDRAWAL,
+            min: None,
+            flat: 0.25,
+            percent: 0.0,
+        });
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_withdrawal = Record {
+            r#type: Type::WITHDRAWAL,
+            tx: 2,
+            amount: Some(9.9),
+            row: 1,
+            ..record_deposit.clone()
+        };
+        let records_map = HashMap::new();
+
+        apply(&record_deposit, &mut ledger, &records_map, &config);
+        let Applied { amount, fee, .. } =
+            apply(&record_withdrawal, &mut ledger, &records_map, &config);
+
+        // the amount is available but not with its fee
+        assert_eq!(amount, None);
+        assert_eq!(fee, 0.0);
+        assert_eq!(ledger.client(1, None).available, 10.0);
+    }
+
+    #[test]
+    fn test_charge_house_client() {
+        let mut ledger = Ledger::default();
+        let config = Config {
+            fees: schedule(),
+            ..Default::default()
+        };
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(100.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_house_deposit = Record {
+            client: HOUSE,
+            tx: 2,
+            amount: Some(10.0),
+            row: 1,
+            ..record_deposit.clone()
+        };
+        let record_house_withdrawal = Record {
+            r#type: Type::WITHDRAWAL,
+            client: HOUSE,
+            tx: 3,
+            amount: Some(10.0),
+            row: 2,
+            ..record_deposit.clone()
+        };
+        let records_map = HashMap::new();
+
+        for record in [record_deposit, record_house_deposit].iter() {
+            apply(record, &mut ledger, &records_map, &config);
+        }
+        let Applied { amount, .. } =
+            apply(&record_house_withdrawal, &mut ledger, &records_map, &config);
+
+        // the client with the id of the house has no access to the fees
+        assert_eq!(amount, None);
+        assert_eq!(ledger.client(HOUSE, None).available, 9.9);
+        assert_eq!(ledger.fees(None), 1.1);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn test_charge_chargeback_penalty() {
+        let mut ledger = Ledger::default();
+        let config = Config {
+            fees: schedule(),
+            ..Default::default()
+        };
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(100.0),
+            status: Some(Status::DISPUTED),
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_dispute = Record {
+            r#type: Type::DISPUTE,
+            client: 1,
+            tx: 1,
+            amount: None,
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_chargeback = Record {
+            r#type: Type::CHARGEBACK,
+            client: 1,
+            tx: 1,
+            amount: None,
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let mut records_map = HashMap::new();
+        records_map.insert(record_deposit.tx, record_deposit.clone());
+
+        for record in [record_deposit, record_dispute, record_chargeback].iter() {
+            apply(record, &mut ledger, &records_map, &config);
+        }
+
+        let client = ledger.client(1, None);
+        assert_eq!(client.available, -16.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, -16.0);
+        assert!(client.locked);
+        assert_eq!(ledger.fees(None), 16.0);
+        assert!(ledger.check().is_ok());
+    }
+
+    // deposits 10, withdraws 2 and disputes the withdrawal, optionally followed by a resolve or chargeback
+    fn withdrawal_dispute(policy: WithdrawalDispute, settle: Option<Type>) -> Client {
+        let mut ledger = Ledger::default();
+        let config = Config {
+            withdrawal_dispute: policy,
+            ..Default::default()
+        };
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_withdrawal = Record {
+            r#type: Type::WITHDRAWAL,
+            client: 1,
+            tx: 2,
+            amount: Some(2.0),
+            status: Some(Status::DISPUTED),
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let mut records = vec![
+            record_deposit.clone(),
+            record_withdrawal.clone(),
+            Record {
+                r#type: Type::DISPUTE,
+                client: 1,
+                tx: 2,
+                amount: None,
+                status: None,
+                timestamp: None,
+                currency: None,
+                to: None,
+                recipient: None,
+                idempotency_key: None,
+                row: 0,
+            },
+        ];
+        if let Some(r#type) = settle {
+            records.push(Record {
+                r#type,
+                client: 1,
+                tx: 2,
+                amount: None,
+                status: None,
+                timestamp: None,
+                currency: None,
+                to: None,
+                recipient: None,
+                idempotency_key: None,
+                row: 0,
+            });
+        }
+
+        let mut records_map = HashMap::new();
+        records_map.insert(record_deposit.tx, record_deposit);
+        records_map.insert(record_withdrawal.tx, record_withdrawal);
+
+        for record in &records {
+            process(record, &mut ledger, &records_map, &config);
+        }
+        assert!(ledger.check().is_ok());
+
+        ledger.client(1, None)
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_disallow() {
+        let policy = WithdrawalDispute::Disallow;
+
+        let client = withdrawal_dispute(policy, None);
+        assert_eq!(client.available, 8.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 8.0);
+
+        let client = withdrawal_dispute(policy, Some(Type::CHARGEBACK));
+        assert_eq!(client.available, 8.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 8.0);
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_reverse_on_chargeback() {
+        let policy = WithdrawalDispute::ReverseOnChargeback;
+
+        let client = withdrawal_dispute(policy, None);
+        assert_eq!(client.available, 8.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 8.0);
+
+        let client = withdrawal_dispute(policy, Some(Type::RESOLVE));
+        assert_eq!(client.available, 8.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 8.0);
+        assert!(!client.locked);
+
+        let client = withdrawal_dispute(policy, Some(Type::CHARGEBACK));
+        assert_eq!(client.available, 10.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 10.0);
+        assert!(client.locked);
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_hold_from_available() {
+        let policy = WithdrawalDispute::HoldFromAvailable;
+
+        let client = withdrawal_dispute(policy, None);
+        assert_eq!(client.available, 6.0);
+        assert_eq!(client.held, 2.0);
+        assert_eq!(client.total, 8.0);
+
+        let client = withdrawal_dispute(policy, Some(Type::RESOLVE));
+        assert_eq!(client.available, 8.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 8.0);
+        assert!(!client.locked);
+
+        let client = withdrawal_dispute(policy, Some(Type::CHARGEBACK));
+        assert_eq!(client.available, 10.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 10.0);
+        assert!(client.locked);
+    }
+
+    fn timestamp(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+        Some(s.parse().expect("Failed to parse timestamp"))
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        let mut ledger = Ledger::default();
+        let config = Config {
+            dispute_window: Some(chrono::Duration::days(120)),
+            ..Default::default()
+        };
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: timestamp("2021-01-01T00:00:00Z"),
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_late = Record {
+            r#type: Type::DISPUTE,
+            client: 1,
+            tx: 1,
+            amount: None,
+            status: None,
+            timestamp: timestamp("2021-06-01T00:00:00Z"),
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_dispute = Record {
+            timestamp: timestamp("2021-03-01T00:00:00Z"),
+            ..record_late.clone()
+        };
+        let mut records_map = HashMap::new();
+        records_map.insert(record_deposit.tx, record_deposit.clone());
+
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        let (client, _amount) = process(&record_late, &mut ledger, &records_map, &config);
+        assert_eq!(client.held, 0.0);
+        assert!(ledger.disputes.is_empty());
+
+        let (client, _amount) = process(&record_dispute, &mut ledger, &records_map, &config);
+        assert_eq!(client.held, 10.0);
+        assert!(ledger.disputes.contains_key(&1));
+    }
+
+    #[test]
+    fn test_dispute_expiry() {
+        let mut ledger = Ledger::default();
+        let config = Config {
+            dispute_expiry: Some(chrono::Duration::days(30)),
+            ..Default::default()
+        };
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: timestamp("2021-01-01T00:00:00Z"),
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_dispute = Record {
+            r#type: Type::DISPUTE,
+            client: 1,
+            tx: 1,
+            amount: None,
+            status: None,
+            timestamp: timestamp("2021-01-02T00:00:00Z"),
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_early = Record {
+            r#type: Type::DEPOSIT,
+            client: 2,
+            tx: 2,
+            amount: Some(1.0),
+            status: None,
+            timestamp: timestamp("2021-01-20T00:00:00Z"),
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_late = Record {
+            tx: 3,
+            timestamp: timestamp("2021-03-01T00:00:00Z"),
+            ..record_early.clone()
+        };
+        let record_chargeback = Record {
+            r#type: Type::CHARGEBACK,
+            client: 1,
+            tx: 1,
+            amount: None,
+            status: None,
+            timestamp: timestamp("2021-03-02T00:00:00Z"),
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let mut records_map = HashMap::new();
+        records_map.insert(record_deposit.tx, record_deposit.clone());
+
+        step(&record_deposit, &mut ledger, &records_map, &config);
+        step(&record_dispute, &mut ledger, &records_map, &config);
+
+        let applied = step(&record_early, &mut ledger, &records_map, &config);
+        assert_eq!(applied.len(), 1);
+        assert_eq!(ledger.client(1, None).held, 10.0);
+
+        let applied = step(&record_late, &mut ledger, &records_map, &config);
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[0].0.r#type, Type::RESOLVE);
+        assert_eq!(applied[0].1.after.available, 10.0);
+        assert_eq!(applied[0].1.after.held, 0.0);
+        assert!(ledger.disputes.is_empty());
+
+        // the dispute was resolved, the chargeback is ignored
+        step(&record_chargeback, &mut ledger, &records_map, &config);
+        let client = ledger.client(1, None);
+        assert_eq!(client.total, 10.0);
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn test_partial_dispute() {
+        let mut ledger = Ledger::default();
+        let config = Config::default();
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_dispute = Record {
+            r#type: Type::DISPUTE,
+            client: 1,
+            tx: 1,
+            amount: Some(4.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_too_much = Record {
+            amount: Some(7.0),
+            ..record_dispute.clone()
+        };
+        let record_rest = Record {
+            amount: None,
+            ..record_dispute.clone()
+        };
+        let record_resolve = Record {
+            r#type: Type::RESOLVE,
+            amount: None,
+            ..record_dispute.clone()
+        };
+        let mut records_map = HashMap::new();
+        records_map.insert(record_deposit.tx, record_deposit.clone());
+
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        let (client, amount) = process(&record_dispute, &mut ledger, &records_map, &config);
+        assert_eq!(amount, Some(4.0));
+        assert_eq!(client.available, 6.0);
+        assert_eq!(client.held, 4.0);
+
+        // only 6 is left to dispute
+        let (client, amount) = process(&record_too_much, &mut ledger, &records_map, &config);
+        assert_eq!(amount, None);
+        assert_eq!(client.held, 4.0);
+
+        let (client, amount) = process(&record_rest, &mut ledger, &records_map, &config);
+        assert_eq!(amount, Some(6.0));
+        assert_eq!(client.available, 0.0);
+        assert_eq!(client.held, 10.0);
+        assert_eq!(ledger.disputes[&1].amount, 10.0);
+
+        let (client, amount) = process(&record_resolve, &mut ledger, &records_map, &config);
+        assert_eq!(amount, Some(10.0));
+        assert_eq!(client.available, 10.0);
+        assert_eq!(client.held, 0.0);
+        assert!(ledger.disputes.is_empty());
+    }
+
+    #[test]
+    fn test_partial_chargeback() {
+        let mut ledger = Ledger::default();
+        let config = Config::default();
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_dispute = Record {
+            r#type: Type::DISPUTE,
+            client: 1,
+            tx: 1,
+            amount: None,
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let record_chargeback = Record {
+            r#type: Type::CHARGEBACK,
+            amount: Some(4.0),
+            ..record_dispute.clone()
+        };
+        let mut records_map = HashMap::new();
+        records_map.insert(record_deposit.tx, record_deposit.clone());
+
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        process(&record_dispute, &mut ledger, &records_map, &config);
+        let (client, amount) = process(&record_chargeback, &mut ledger, &records_map, &config);
+        assert_eq!(amount, Some(4.0));
+        assert_eq!(client.available, 0.0);
+        assert_eq!(client.held, 6.0);
+        assert_eq!(client.total, 6.0);
+        assert!(client.locked);
+
+        // the rest stays under dispute, what was charged back can not be disputed again
+        assert_eq!(ledger.disputes[&1].amount, 6.0);
+        assert_eq!(ledger.disputable(1, 10.0), 0.0);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn test_redispute() {
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 1,
+        };
+        let record_dispute = Record {
+            r#type: Type::DISPUTE,
+            amount: None,
+            row: 2,
+            ..record_deposit.clone()
+        };
+        let record_resolve = Record {
+            r#type: Type::RESOLVE,
+            amount: None,
+            row: 3,
+            ..record_deposit.clone()
+        };
+        let record_redispute = Record {
+            row: 4,
+            ..record_dispute.clone()
+        };
+        let mut records_map = HashMap::new();
+        records_map.insert(record_deposit.tx, record_deposit.clone());
+
+        for (redispute, held) in [(Redispute::Allow, 10.0), (Redispute::Never, 0.0)].iter() {
+            let mut ledger = Ledger::default();
+            let config = Config {
+                redispute: *redispute,
+                ..Default::default()
+            };
+
+            for record in [&record_deposit, &record_dispute, &record_resolve].iter() {
+                process(record, &mut ledger, &records_map, &config);
+            }
+            let (client, _amount) = process(&record_redispute, &mut ledger, &records_map, &config);
+            assert_eq!(client.held, *held);
+
+            let lifecycle = &ledger.lifecycles[&1];
+            assert_eq!(lifecycle.events[0].row, 2);
+            assert_eq!(lifecycle.events[1].status, Status::RESOLVED);
+            assert_eq!(lifecycle.resolved(), 1);
+        }
+    }
+
+    #[test]
+    fn test_currencies() {
+        let mut ledger = Ledger::default();
+        let config = Config::default();
+        let eur = "EUR".parse().ok();
+        let usd = "USD".parse().ok();
+        let record_eur = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: eur,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 1,
+        };
+        let record_usd = Record {
+            tx: 2,
+            amount: Some(4.0),
+            currency: usd,
+            to: None,
+            recipient: None,
+            row: 2,
+            ..record_eur.clone()
+        };
+        let record_dispute = Record {
+            r#type: Type::DISPUTE,
+            tx: 1,
+            amount: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            row: 3,
+            ..record_eur.clone()
+        };
+        let record_wrong_currency = Record {
+            currency: usd,
+            to: None,
+            recipient: None,
+            row: 4,
+            ..record_dispute.clone()
+        };
+        let mut records_map = HashMap::new();
+        records_map.insert(record_eur.tx, record_eur.clone());
+        records_map.insert(record_usd.tx, record_usd.clone());
+
+        process(&record_eur, &mut ledger, &records_map, &config);
+        process(&record_usd, &mut ledger, &records_map, &config);
+        assert_eq!(ledger.client(1, eur).total, 10.0);
+        assert_eq!(ledger.client(1, usd).total, 4.0);
+
+        // the dispute is in the currency of the tx
+        let (client, amount) = process(&record_dispute, &mut ledger, &records_map, &config);
+        assert_eq!(amount, Some(10.0));
+        assert_eq!(client.currency, eur);
+        assert_eq!(client.held, 10.0);
+
+        let (client, amount) = process(&record_wrong_currency, &mut ledger, &records_map, &config);
+        assert_eq!(amount, None);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(ledger.client(1, usd).available, 4.0);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn test_convert() {
+        let mut ledger = Ledger::default();
+        let eur = "EUR".parse().ok();
+        let usd = "USD".parse().ok();
+        let mut config = Config::default();
+        config.rates.insert(crate::fx::Rate {
+            date: chrono::NaiveDate::from_ymd_opt(2021, 1, 1).expect("Failed to create date"),
+            pair: crate::fx::Pair {
+                from: "EUR".parse().expect("Failed to parse currency"),
+                to: "USD".parse().expect("Failed to parse currency"),
+            },
+            rate: 1.5,
+        });
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: eur,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 1,
+        };
+        let record_convert = Record {
+            r#type: Type::CONVERT,
+            tx: 2,
+            amount: Some(4.0),
+            to: usd,
+            recipient: None,
+            row: 2,
+            ..record_deposit.clone()
+        };
+        let record_no_rate = Record {
+            tx: 3,
+            to: "GBP".parse().ok(),
+            recipient: None,
+            row: 3,
+            ..record_convert.clone()
+        };
+        let records_map = HashMap::new();
+
+        step(&record_deposit, &mut ledger, &records_map, &config);
+        let applied = step(&record_convert, &mut ledger, &records_map, &config);
+
+        // the debit and the credit are applied in their own currency
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[0].1.after.available, 6.0);
+        assert_eq!(applied[1].0.currency, usd);
+        assert_eq!(applied[1].1.amount, Some(6.0));
+        assert_eq!(applied[1].1.before.total, 0.0);
+        assert_eq!(ledger.client(1, usd).total, 6.0);
+        assert_eq!(ledger.balance((HOUSE, Account::Exchange), eur), 4.0);
+        assert!(ledger.check().is_ok());
+
+        let applied = step(&record_no_rate, &mut ledger, &records_map, &config);
+        assert_eq!(applied.len(), 1);
+        assert_eq!(applied[0].1.amount, None);
+        assert_eq!(ledger.client(1, eur).total, 6.0);
+    }
+
+    fn transfer_records() -> (Record, Record, RecordMap) {
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 1,
+        };
+        let record_transfer = Record {
+            r#type: Type::TRANSFER,
+            tx: 2,
+            amount: Some(4.0),
+            recipient: Some(2),
+            row: 2,
+            ..record_deposit.clone()
+        };
+        let mut records_map = HashMap::new();
+        records_map.insert(record_deposit.tx, record_deposit.clone());
+        records_map.insert(record_transfer.tx, record_transfer.clone());
+
+        (record_deposit, record_transfer, records_map)
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut ledger = Ledger::default();
+        let config = Config::default();
+        let (record_deposit, record_transfer, records_map) = transfer_records();
+        let record_too_much = Record {
+            tx: 3,
+            amount: Some(7.0),
+            ..record_transfer.clone()
+        };
+        let record_to_self = Record {
+            tx: 4,
+            recipient: Some(1),
+            ..record_transfer.clone()
+        };
+
+        step(&record_deposit, &mut ledger, &records_map, &config);
+        let applied = step(&record_transfer, &mut ledger, &records_map, &config);
+
+        // the sender and the recipient are both applied
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[0].1.after.available, 6.0);
+        assert_eq!(applied[1].0.client, 2);
+        assert_eq!(applied[1].1.after.available, 4.0);
+
+        let applied = step(&record_too_much, &mut ledger, &records_map, &config);
+        assert_eq!(applied.len(), 1);
+        assert_eq!(applied[0].1.amount, None);
+
+        let (_client, amount) = process(&record_to_self, &mut ledger, &records_map, &config);
+        assert_eq!(amount, None);
+
+        // a locked recipient can not receive a transfer
+        ledger.lock(2);
+        let (client, amount) = process(&record_transfer, &mut ledger, &records_map, &config);
+        assert_eq!(amount, None);
+        assert_eq!(client.available, 6.0);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn test_transfer_chargeback() {
+        let mut ledger = Ledger::default();
+        let config = Config::default();
+        let (record_deposit, record_transfer, records_map) = transfer_records();
+        let record_dispute = Record {
+            r#type: Type::DISPUTE,
+            amount: None,
+            recipient: None,
+            ..record_transfer.clone()
+        };
+        let record_chargeback = Record {
+            r#type: Type::CHARGEBACK,
+            ..record_dispute.clone()
+        };
+
+        step(&record_deposit, &mut ledger, &records_map, &config);
+        step(&record_transfer, &mut ledger, &records_map, &config);
+
+        // the transfer is held at the recipient
+        let applied = step(&record_dispute, &mut ledger, &records_map, &config);
+        assert_eq!(applied.len(), 2);
+        assert_eq!(ledger.client(1, None).available, 6.0);
+        assert_eq!(ledger.client(2, None).held, 4.0);
+
+        // and charged back to the sender as a unit
+        step(&record_chargeback, &mut ledger, &records_map, &config);
+        let sender = ledger.client(1, None);
+        let recipient = ledger.client(2, None);
+        assert_eq!(sender.available, 10.0);
+        assert!(!sender.locked);
+        assert_eq!(recipient.total, 0.0);
+        assert!(recipient.locked);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut ledger = Ledger::default();
+        let mut config = Config::default();
+        config.limits.default.max_amount = Some(100.0);
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(50.0),
+            status: None,
+            timestamp: timestamp("2021-01-01T00:00:00Z"),
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 1,
+        };
+        let record_too_much = Record {
+            tx: 2,
+            amount: Some(150.0),
+            row: 2,
+            ..record_deposit.clone()
+        };
+        let records_map = HashMap::new();
+
+        let (_client, amount) = process(&record_deposit, &mut ledger, &records_map, &config);
+        assert_eq!(amount, Some(50.0));
+        assert_eq!(ledger.activity[&(1, None)].len(), 1);
+
+        let (client, amount) = process(&record_too_much, &mut ledger, &records_map, &config);
+        assert_eq!(amount, None);
+        assert_eq!(client.total, 50.0);
+        assert_eq!(ledger.rejections.len(), 1);
+        assert_eq!(ledger.rejections[0].row, 2);
+        assert_eq!(
+            ledger.rejections[0].reason,
+            crate::limits::Reason::MaxAmount
+        );
+    }
+
+    #[test]
+    fn test_limits_currency() {
+        let mut ledger = Ledger::default();
+        let mut config = Config::default();
+        config.limits.default.daily_withdrawal = Some(100.0);
+        let record = |r#type, tx, currency: &str, amount| Record {
+            r#type,
+            client: 1,
+            tx,
+            amount: Some(amount),
+            status: None,
+            timestamp: timestamp("2021-01-01T00:00:00Z"),
+            currency: currency.parse().ok(),
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 0,
+        };
+        let records_map = HashMap::new();
+
+        process(
+            &record(Type::DEPOSIT, 1, "EUR", 200.0),
+            &mut ledger,
+            &records_map,
+            &config,
+        );
+        process(
+            &record(Type::DEPOSIT, 2, "USD", 200.0),
+            &mut ledger,
+            &records_map,
+            &config,
+        );
+
+        // the withdrawals of another currency do not count against the daily cap
+        let (_client, amount) = process(
+            &record(Type::WITHDRAWAL, 3, "EUR", 80.0),
+            &mut ledger,
+            &records_map,
+            &config,
+        );
+        assert_eq!(amount, Some(80.0));
+        let (_client, amount) = process(
+            &record(Type::WITHDRAWAL, 4, "USD", 80.0),
+            &mut ledger,
+            &records_map,
+            &config,
+        );
+        assert_eq!(amount, Some(80.0));
+        let (_client, amount) = process(
+            &record(Type::WITHDRAWAL, 5, "EUR", 30.0),
+            &mut ledger,
+            &records_map,
+            &config,
+        );
+        assert_eq!(amount, None);
+        assert_eq!(
+            ledger.rejections[0].reason,
+            crate::limits::Reason::DailyWithdrawal
+        );
+    }
+
+    #[test]
+    fn test_risk() {
+        let mut ledger = Ledger::default();
+        let mut config = Config::default();
+        config.risk.rules = vec![(crate::risk::Rule::QuickWithdrawal { window: 30 }, 50.0)];
+        config.risk.hold = Some(50.0);
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(100.0),
+            status: None,
+            timestamp: timestamp("2021-01-01T00:00:00Z"),
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 1,
+        };
+        let record_withdrawal = Record {
+            r#type: Type::WITHDRAWAL,
+            tx: 2,
+            amount: Some(40.0),
+            timestamp: timestamp("2021-01-01T00:10:00Z"),
+            row: 2,
+            ..record_deposit.clone()
+        };
+        let records_map = HashMap::new();
+
+        process(&record_deposit, &mut ledger, &records_map, &config);
+        assert!(ledger.flags.is_empty());
+
+        // the withdrawal right after the deposit is held instead of paid out
+        let (client, amount) = process(&record_withdrawal, &mut ledger, &records_map, &config);
+        assert_eq!(amount, Some(40.0));
+        assert_eq!(client.available, 60.0);
+        assert_eq!(client.held, 40.0);
+        assert_eq!(client.total, 100.0);
+        assert_eq!(ledger.flags.len(), 1);
+        assert_eq!(ledger.flags[0].action, crate::risk::Action::Hold);
+        assert_eq!(ledger.flags[0].rules, "quick-withdrawal");
+
+        // and rejected once the score reaches the reject threshold
+        config.risk.reject = Some(50.0);
+        let record_withdrawal = Record {
+            tx: 3,
+            row: 3,
+            ..record_withdrawal
+        };
+        let (client, amount) = process(&record_withdrawal, &mut ledger, &records_map, &config);
+        assert_eq!(amount, None);
+        assert_eq!(client.available, 60.0);
+        assert_eq!(ledger.flags[1].action, crate::risk::Action::Reject);
+    }
+
+    #[test]
+    fn test_risk_review() {
+        let mut ledger = Ledger::default();
+        let mut config = Config::default();
+        config.risk.rules = vec![(crate::risk::Rule::QuickWithdrawal { window: 30 }, 50.0)];
+        config.risk.hold = Some(50.0);
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(100.0),
+            status: None,
+            timestamp: timestamp("2021-01-01T00:00:00Z"),
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 1,
+        };
+        let record_withdrawal = Record {
+            r#type: Type::WITHDRAWAL,
+            tx: 2,
+            amount: Some(10.0),
+            timestamp: timestamp("2021-01-01T00:10:00Z"),
+            row: 2,
+            ..record_deposit.clone()
+        };
+        let record_dispute = Record {
+            r#type: Type::DISPUTE,
+            amount: None,
+            row: 3,
+            ..record_withdrawal.clone()
+        };
+        let record_chargeback = Record {
+            r#type: Type::CHARGEBACK,
+            row: 4,
+            ..record_dispute.clone()
+        };
+        let mut records_map = HashMap::new();
+        records_map.insert(record_deposit.tx, record_deposit.clone());
+        records_map.insert(record_withdrawal.tx, record_withdrawal.clone());
+        config.withdrawal_dispute = WithdrawalDispute::HoldFromAvailable;
+
+        for record in [&record_deposit, &record_withdrawal] {
+            process(record, &mut ledger, &records_map, &config);
+        }
+
+        // a record on hold can not be disputed
+        let (_client, amount) = process(&record_dispute, &mut ledger, &records_map, &config);
+        assert_eq!(amount, None);
+        let (client, amount) = process(&record_chargeback, &mut ledger, &records_map, &config);
+        assert_eq!(amount, None);
+        assert_eq!(client.available, 90.0);
+        assert_eq!(client.held, 10.0);
+        assert_eq!(client.total, 100.0);
+
+        // until it is released, the withdrawal is paid out
+        let record_release = Record {
+            r#type: Type::RELEASE,
+            row: 5,
+            ..record_dispute.clone()
+        };
+        let (client, amount) = process(&record_release, &mut ledger, &records_map, &config);
+        assert_eq!(amount, Some(10.0));
+        assert_eq!(client.available, 90.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 90.0);
+        let (_client, amount) = process(&record_release, &mut ledger, &records_map, &config);
+        assert_eq!(amount, None);
+        let (client, amount) = process(&record_dispute, &mut ledger, &records_map, &config);
+        assert_eq!(amount, Some(10.0));
+        assert_eq!(client.held, 10.0);
+
+        // a rejected withdrawal is cancelled and never disputed
+        let record_withdrawal = Record {
+            tx: 3,
+            row: 6,
+            ..record_withdrawal
+        };
+        records_map.insert(record_withdrawal.tx, record_withdrawal.clone());
+        process(&record_withdrawal, &mut ledger, &records_map, &config);
+        assert_eq!(ledger.client(1, None).available, 70.0);
+        let record_reject = Record {
+            r#type: Type::REJECT,
+            tx: 3,
+            row: 7,
+            ..record_release
+        };
+        let (client, amount) = process(&record_reject, &mut ledger, &records_map, &config);
+        assert_eq!(amount, Some(10.0));
+        assert_eq!(client.available, 80.0);
+        assert_eq!(client.held, 10.0);
+        let record_dispute = Record {
+            tx: 3,
+            row: 8,
+            ..record_dispute
+        };
+        let (_client, amount) = process(&record_dispute, &mut ledger, &records_map, &config);
+        assert_eq!(amount, None);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn test_resubmission() {
+        let mut ledger = Ledger::default();
+        let config = Config::default();
+        let csv = crate::csv_reader::CSV::parse(
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,1,1,10.0\n\
+             deposit,1,1,99.0\n",
+            crate::csv_reader::Format::Csv,
+            crate::csv_reader::Mode::Strict,
+        )
+        .expect("Failed to load records");
+        assert_eq!(csv.records_map[&1].amount, Some(10.0));
+
+        let applied: Vec<usize> = csv
+            .records_vec
+            .iter()
+            .map(|record| step(record, &mut ledger, &csv.records_map, &config).len())
+            .collect();
+
+        // the same record again is not applied twice, another amount for the tx is a conflict
+        assert_eq!(applied, vec![1, 0, 0]);
+        assert_eq!(ledger.client(1, None).total, 10.0);
+        assert_eq!(
+            ledger.conflicts,
+            vec![Conflict {
+                row: 3,
+                client: 1,
+                submission: Submission::Tx(1),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_clearing() {
+        let mut ledger = Ledger::default();
+        let config = Config {
+            clearing_rows: Some(1),
+            ..Default::default()
+        };
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 1,
+        };
+        let record_withdrawal = Record {
+            r#type: Type::WITHDRAWAL,
+            tx: 2,
+            amount: Some(5.0),
+            row: 2,
+            ..record_deposit.clone()
+        };
+        let records_map = HashMap::new();
+
+        step(&record_deposit, &mut ledger, &records_map, &config);
+        let client = ledger.client(1, None);
+        assert_eq!(client.available, 0.0);
+        assert_eq!(client.held, 10.0);
+        assert_eq!(client.total, 10.0);
+
+        // the deposit has not cleared yet
+        let applied = step(&record_withdrawal, &mut ledger, &records_map, &config);
+        assert_eq!(applied.len(), 1);
+        assert_eq!(applied[0].1.amount, None);
+
+        // the deposit clears before the second row after it
+        let record_withdrawal = Record {
+            tx: 3,
+            row: 3,
+            ..record_withdrawal
+        };
+        let applied = step(&record_withdrawal, &mut ledger, &records_map, &config);
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[0].0.r#type, Type::CLEAR);
+        assert_eq!(applied[0].1.after.available, 10.0);
+        assert_eq!(applied[1].1.amount, Some(5.0));
+        assert!(ledger.pending.is_empty());
+        assert_eq!(ledger.client(1, None).available, 5.0);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn test_clearing_period() {
+        let mut ledger = Ledger::default();
+        let config = Config {
+            clearing_period: Some(chrono::Duration::days(2)),
+            ..Default::default()
+        };
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(10.0),
+            status: None,
+            timestamp: timestamp("2021-01-01T00:00:00Z"),
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 1,
+        };
+        let record_early = Record {
+            client: 2,
+            tx: 2,
+            timestamp: timestamp("2021-01-02T00:00:00Z"),
+            row: 2,
+            ..record_deposit.clone()
+        };
+        let record_late = Record {
+            client: 2,
+            tx: 3,
+            timestamp: timestamp("2021-01-03T00:00:00Z"),
+            row: 3,
+            ..record_deposit.clone()
+        };
+        let records_map = HashMap::new();
+
+        step(&record_deposit, &mut ledger, &records_map, &config);
+        assert_eq!(
+            step(&record_early, &mut ledger, &records_map, &config).len(),
+            1
+        );
+
+        let applied = step(&record_late, &mut ledger, &records_map, &config);
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[0].0.tx, 1);
+        assert_eq!(ledger.client(1, None).available, 10.0);
+        assert_eq!(ledger.client(2, None).available, 0.0);
+        assert_eq!(ledger.pending.len(), 2);
+    }
+
+    #[test]
+    fn test_clearing_dispute() {
+        let mut ledger = Ledger::default();
+        let config = Config {
+            clearing_rows: Some(2),
+            fees: schedule(),
+            ..Default::default()
+        };
+        let record_deposit = Record {
+            r#type: Type::DEPOSIT,
+            client: 1,
+            tx: 1,
+            amount: Some(100.0),
+            status: None,
+            timestamp: None,
+            currency: None,
+            to: None,
+            recipient: None,
+            idempotency_key: None,
+            row: 1,
+        };
+        let record_dispute = Record {
+            r#type: Type::DISPUTE,
+            amount: Some(40.0),
+            row: 2,
+            ..record_deposit.clone()
+        };
+        let record_resolve = Record {
+            r#type: Type::RESOLVE,
+            amount: None,
+            row: 3,
+            ..record_deposit.clone()
+        };
+        let mut records_map = HashMap::new();
+        records_map.insert(record_deposit.tx, record_deposit.clone());
+
+        // the deposit fee and the disputed funds are taken from pending
+        let applied = step(&record_deposit, &mut ledger, &records_map, &config);
+        let fee = applied[0].1.fee;
+        assert!(fee > 0.0);
+        step(&record_dispute, &mut ledger, &records_map, &config);
+        let client = ledger.client(1, None);
+        assert_eq!(client.available, 0.0);
+        assert_eq!(client.total, 100.0 - fee);
+        assert_eq!(ledger.pending[&1].amount, 60.0 - fee);
+
+        // the resolved funds return to pending and clear with the deposit
+        step(&record_resolve, &mut ledger, &records_map, &config);
+        assert_eq!(ledger.pending[&1].amount, 100.0 - fee);
+        assert_eq!(ledger.client(1, None).available, 0.0);
+
+        let record_next = Record {
+            tx: 2,
+            amount: Some(1.0),
+            row: 4,
+            ..record_deposit.clone()
+        };
+        step(&record_next, &mut ledger, &records_map, &config);
+        assert_eq!(ledger.client(1, None).available, 100.0 - fee);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn test_funded() {
+        let eur = "EUR".parse().ok();
+        for r#type in [Type::WITHDRAWAL, Type::CONVERT, Type::TRANSFER] {
+            let mut ledger = Ledger::default();
+            let mut config = Config::default();
+            config.fees.insert(Fee {
+                r#type,
+                min: None,
+                flat: 1.0,
+                percent: 0.0,
+            });
+            config.rates.insert(crate::fx::Rate {
+                date: chrono::NaiveDate::from_ymd_opt(2021, 1, 1).expect("Failed to create date"),
+                pair: crate::fx::Pair {
+                    from: "EUR".parse().expect("Failed to parse currency"),
+                    to: "USD".parse().expect("Failed to parse currency"),
+                },
+                rate: 1.5,
+            });
+            let record_deposit = Record {
+                r#type: Type::DEPOSIT,
+                client: 1,
+                tx: 1,
+                amount: Some(10.0),
+                status: None,
+                timestamp: None,
+                currency: eur,
+                to: None,
+                recipient: None,
+                idempotency_key: None,
+                row: 0,
+            };
+            let record = |tx, amount| Record {
+                r#type,
+                client: 1,
+                tx,
+                amount: Some(amount),
+                status: None,
+                timestamp: None,
+                currency: eur,
+                to: "USD".parse().ok(),
+                recipient: Some(2),
+                idempotency_key: None,
+                row: 0,
+            };
+            let records_map = HashMap::new();
+
+            step(&record_deposit, &mut ledger, &records_map, &config);
+
+            // exactly the amount and its fee is not enough
+            let applied = step(&record(2, 9.0), &mut ledger, &records_map, &config);
+            assert_eq!(applied[0].1.amount, None, "{}", r#type);
+            assert_eq!(ledger.client(1, eur).available, 10.0, "{}", r#type);
+            let applied = step(&record(3, 8.5), &mut ledger, &records_map, &config);
+            assert_eq!(applied[0].1.amount, Some(8.5), "{}", r#type);
+            assert_eq!(ledger.client(1, eur).available, 0.5, "{}", r#type);
+            assert!(ledger.check().is_ok());
+        }
+    }
 }
@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::{self, Write};
+
+use crate::config::Config;
+use crate::csv_reader::{Format, Mode, Type, CSV};
+use crate::diagnostic::Malformed;
+use crate::ledger::Ledger;
+use crate::transction;
+
+// What a dry run of a file found, nothing is written
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    // number of records of every type
+    pub types: HashMap<Type, usize>,
+    // number of records that were not applied by reason, `ignored` without a reason
+    pub rejections: HashMap<String, usize>,
+    // clients whose first record is not a deposit, with the line of that record
+    pub unknown_clients: Vec<(u16, usize)>,
+    // deposits, withdrawals, converts and transfers with the tx of an earlier one, with their line
+    pub duplicates: Vec<(u32, usize)>,
+    pub malformed: Vec<Malformed>,
+}
+
+impl Summary {
+    // Returns true if records were rejected by the limits or the risk rules or were a conflict,
+    // records ignored without a reason are applied the same way when the file is processed
+    pub fn rejected(&self) -> bool {
+        self.rejections.keys().any(|reason| reason != "ignored")
+    }
+
+    // Returns true if the file has anything that needs a look before it is sent
+    pub fn problems(&self) -> bool {
+        self.rejected()
+            || !self.unknown_clients.is_empty()
+            || !self.duplicates.is_empty()
+            || !self.malformed.is_empty()
+    }
+
+    // Writes the summary as plain text
+    pub fn write_text<W: Write>(&self, mut writer: W) -> Result<(), Box<dyn Error>> {
+        let records: usize = self.types.values().sum();
+        writeln!(writer, "Records: {}", records)?;
+        let mut types: Vec<(String, usize)> = self
+            .types
+            .iter()
+            .map(|(r#type, count)| (r#type.to_string(), *count))
+            .collect();
+        types.sort();
+        for (r#type, count) in types {
+            writeln!(writer, "  {:<12} {:>8}", r#type, count)?;
+        }
+
+        if !self.rejections.is_empty() {
+            writeln!(writer, "Rejections:")?;
+            let mut rejections: Vec<(&String, &usize)> = self.rejections.iter().collect();
+            rejections.sort();
+            for (reason, count) in rejections {
+                writeln!(writer, "  {:<20} {:>8}", reason, count)?;
+            }
+        }
+
+        if !self.unknown_clients.is_empty() {
+            writeln!(writer, "Unknown clients:")?;
+            for (client, line) in &self.unknown_clients {
+                writeln!(writer, "  client {} at line {}", client, line)?;
+            }
+        }
+
+        if !self.duplicates.is_empty() {
+            writeln!(writer, "Duplicate tx ids:")?;
+            for (tx, line) in &self.duplicates {
+                writeln!(writer, "  tx {} at line {}", tx, line)?;
+            }
+        }
+
+        if !self.malformed.is_empty() {
+            writeln!(writer, "Malformed lines:")?;
+            for malformed in &self.malformed {
+                for line in malformed.to_string().lines() {
+                    writeln!(writer, "  {}", line)?;
+                }
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+// Applies the records of a lenient load without writing anything, returns what was found
+pub fn validate(csv: &CSV, config: &Config) -> Summary {
+    let mut summary = Summary {
+        malformed: csv.malformed.clone(),
+        ..Default::default()
+    };
+
+    let mut clients = HashSet::new();
+    let mut txs = HashSet::new();
+    for (record, line) in csv.records_vec.iter().zip(&csv.lines) {
+        *summary.types.entry(record.r#type).or_default() += 1;
+
+        if clients.insert(record.client) && record.r#type != Type::DEPOSIT {
+            summary.unknown_clients.push((record.client, *line));
+        }
+
+        let makes_tx = matches!(
+            record.r#type,
+            Type::DEPOSIT | Type::WITHDRAWAL | Type::CONVERT | Type::TRANSFER
+        );
+        if makes_tx && !txs.insert(record.tx) {
+            summary.duplicates.push((record.tx, *line));
+        }
+    }
+
+    // the same steps as processing the file
+    let mut ledger = Ledger::default();
+    for record in &csv.records_vec {
+        let applied = transction::step(record, &mut ledger, &csv.records_map, config);
+        if let Some((_record, result)) = transction::own(record, applied) {
+            if result.amount.is_none() {
+                let reason = ledger
+                    .reason(record.row)
+                    .unwrap_or_else(|| "ignored".to_string());
+                *summary.rejections.entry(reason).or_default() += 1;
+            }
+        }
+    }
+
+    // records with the tx or idempotency key of an earlier one with other contents are not applied
+    if !ledger.conflicts.is_empty() {
+        summary
+            .rejections
+            .insert("conflict".to_string(), ledger.conflicts.len());
+    }
+
+    summary
+}
+
+// Validates the file at the path and writes the summary to the stdout. Malformed lines are skipped
+// instead of stopping the run, unless there are more than the maximum.
+pub fn output(
+    path: &str,
+    format: Format,
+    max_errors: Option<usize>,
+    config: &Config,
+) -> Result<Summary, Box<dyn Error>> {
+    let csv = CSV::open(path, format, Mode::Lenient(max_errors))?;
+    let summary = validate(&csv, config);
+    summary.write_text(io::stdout().lock())?;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut config = Config::default();
+        config.limits.default.max_amount = Some(100.0);
+
+        let csv = CSV::parse(
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             depositt,1,2,1.0\n\
+             withdrawal,2,3,5.0\n\
+             deposit,1,1,5.0\n\
+             deposit,3,4,500.0\n\
+             withdrawal,1,5,50.0\n\
+             dispute,1,1\n\
+             deposit,70000,6,1.0\n",
+            Format::Csv,
+            Mode::Lenient(None),
+        )
+        .expect("Failed to load records");
+        let summary = validate(&csv, &config);
+
+        assert_eq!(summary.types[&Type::DEPOSIT], 3);
+        assert_eq!(summary.types[&Type::WITHDRAWAL], 2);
+        assert_eq!(summary.types.get(&Type::DISPUTE), None);
+        assert_eq!(summary.rejections["max-amount"], 1);
+        assert_eq!(summary.rejections["ignored"], 2);
+        assert_eq!(summary.rejections["conflict"], 1);
+        assert_eq!(summary.unknown_clients, vec![(2, 4)]);
+        assert_eq!(summary.duplicates, vec![(1, 5)]);
+        let lines: Vec<usize> = summary.malformed.iter().map(|m| m.line).collect();
+        assert_eq!(lines, vec![3, 8, 9]);
+        assert!(summary.rejected());
+        assert!(summary.problems());
+
+        let mut output = vec![];
+        summary
+            .write_text(&mut output)
+            .expect("Failed to write summary");
+        let output = String::from_utf8(output).expect("Failed to read summary");
+        assert!(output.starts_with("Records: 5\n"));
+        assert!(output.contains("  Error: Unknown type `depositt`"));
+        assert!(output.contains("   --> line 3, field `type`\n"));
+        assert!(output.contains("  3 | depositt,1,2,1.0\n"));
+    }
+
+    #[test]
+    fn test_validate_sample() {
+        let csv = CSV::open("sample.csv", Format::Csv, Mode::Lenient(None))
+            .expect("Failed to load records");
+        let summary = validate(&csv, &Config::default());
+
+        assert_eq!(summary.types.values().sum::<usize>(), 17);
+        assert!(summary.malformed.is_empty());
+        assert!(summary.duplicates.is_empty());
+        // the withdrawal without enough funds is not a problem
+        assert_eq!(summary.rejections["ignored"], 1);
+        assert!(!summary.rejected());
+        assert!(!summary.problems());
+    }
+}